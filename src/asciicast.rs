@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+// Writes terminal output in the asciicast v2 format used by asciinema. The
+// clock only moves when told to, so recordings have exact timing no matter how
+// long it took to produce the output.
+pub struct Recorder<W: Write> {
+    out: W,
+    time: f64,
+    idle_time_limit: f64,
+}
+
+impl<W: Write> Recorder<W> {
+    // Idle time is capped at idle_time_limit seconds.
+    pub fn new(mut out: W, width: usize, height: usize, idle_time_limit: f64) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        writeln!(
+            out,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"idle_time_limit\": {}}}",
+            width, height, timestamp, idle_time_limit
+        )?;
+        Ok(Recorder {
+            out,
+            time: 0.0,
+            idle_time_limit,
+        })
+    }
+
+    // The data is recorded as it is, so newlines should come with carriage
+    // returns like a terminal would output.
+    pub fn output(&mut self, data: &str) -> io::Result<()> {
+        write!(self.out, "[{:.6}, \"o\", \"", self.time)?;
+        write_escaped(&mut self.out, data)?;
+        writeln!(self.out, "\"]")
+    }
+
+    // Moves the clock forward while output is on display.
+    pub fn wait(&mut self, secs: f64) {
+        self.time += secs;
+    }
+
+    // Moves the clock forward while nothing is happening, at most by the idle
+    // time limit.
+    pub fn idle(&mut self, secs: f64) {
+        self.time += f64::min(secs, self.idle_time_limit);
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// The width and height of the terminal that output would fill, starting at the
+// top left corner. Escape sequences take no room, and the cursor ends up on a
+// row of its own after a final newline.
+pub fn frame_size(data: &str) -> (usize, usize) {
+    let mut width = 0;
+    let mut height = 1;
+    let mut column = 0;
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // Skip to the final byte of a control sequence.
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\n' => {
+                height += 1;
+                column = 0;
+            }
+            '\r' => column = 0,
+            c if (c as u32) < 0x20 => {}
+            _ => {
+                column += 1;
+                width = usize::max(width, column);
+            }
+        }
+    }
+    (width, height)
+}
+
+fn write_escaped<W: Write>(out: &mut W, data: &str) -> io::Result<()> {
+    for c in data.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_events() {
+        let mut buf = Vec::new();
+        {
+            let mut recorder = Recorder::new(&mut buf, 5, 3, 1.0).unwrap();
+            recorder.output("\x1b[H\"a\"\n").unwrap();
+            recorder.wait(0.05);
+            recorder.idle(30.0);
+            recorder.output("\\\x07").unwrap();
+            assert!((recorder.time() - 1.05).abs() < 1e-9);
+        }
+        let text = String::from_utf8(buf).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"version\": 2, \"width\": 5, \"height\": 3, "));
+        assert!(lines[0].ends_with("\"idle_time_limit\": 1}"));
+        assert_eq!(lines[1], "[0.000000, \"o\", \"\\u001b[H\\\"a\\\"\\n\"]");
+        assert_eq!(lines[2], "[1.050000, \"o\", \"\\\\\\u0007\"]");
+    }
+
+    #[test]
+    fn measures_frames() {
+        assert_eq!(frame_size(""), (0, 1));
+        assert_eq!(frame_size("\x1b[H\x1b[Jab\nabcd\n"), (4, 3));
+        assert_eq!(frame_size("abc\r\nde\x07"), (3, 2));
+    }
+}
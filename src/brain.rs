@@ -1,110 +1,877 @@
+use crate::bytes::{
+    at_end, read_f32, read_i32, read_u32, read_u8, write_f32, write_i32, write_u32, write_u8,
+};
 use crate::racetrack::Racetrack;
 use crate::rng::Rng;
 use crate::vector::Vector;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::iter;
+use std::path::Path;
+use std::str::FromStr;
 
-// XXX: Change repeat_array when you change this.
-const N_MID_WEIGHTS: usize = 32;
-
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
 pub struct Brain {
     view_dist: i32,
-    mid_weights: [Vec<f32>; N_MID_WEIGHTS],
-    out_weights: [[f32; N_MID_WEIGHTS]; 5],
+    sensors: Sensors,
+    actions: Actions,
+    // The hidden layers followed by the output layer.
+    layers: Vec<Layer>,
 }
 
-impl Brain {
-    pub fn random(view_dist: i32, rng: &mut Rng) -> Brain {
-        let view_dist = pos_round_up_2(i32::abs(view_dist));
-        let n_inputs = ((view_dist / 2) * (view_dist / 2) * 4) as usize - 2;
-        Brain {
-            view_dist,
-            mid_weights: repeat_array(|| {
-                iter::repeat_with(|| random_f32(rng))
-                    .take(n_inputs)
-                    .collect()
-            }),
-            out_weights: [
-                repeat_array(|| random_f32(rng)),
-                repeat_array(|| random_f32(rng)),
-                repeat_array(|| random_f32(rng)),
-                repeat_array(|| random_f32(rng)),
-                repeat_array(|| random_f32(rng)),
-            ],
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Activation {
+    Linear,
+    Tanh,
+    Relu,
+    Sigmoid,
+    Step,
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Activation::Linear => x,
+            Activation::Tanh => x.tanh(),
+            Activation::Relu => x.max(0.0),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+            Activation::Step => {
+                if x > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
         }
     }
 
-    pub fn mutant(&self, rng: &mut Rng, amount: f64) -> Brain {
-        let amount = amount as f32;
-        let mut mutant = self.clone();
-        for neuron in mutant.mid_weights.iter_mut() {
-            for weight in neuron {
-                *weight += random_f32(rng) * amount;
-            }
+    fn code(self) -> u8 {
+        match self {
+            Activation::Linear => 0,
+            Activation::Tanh => 1,
+            Activation::Relu => 2,
+            Activation::Sigmoid => 3,
+            Activation::Step => 4,
         }
-        for neuron in mutant.out_weights.iter_mut() {
-            for weight in neuron {
-                *weight += random_f32(rng) * amount;
-            }
+    }
+
+    fn from_code(code: u8) -> Option<Activation> {
+        match code {
+            0 => Some(Activation::Linear),
+            1 => Some(Activation::Tanh),
+            2 => Some(Activation::Relu),
+            3 => Some(Activation::Sigmoid),
+            4 => Some(Activation::Step),
+            _ => None,
         }
-        mutant
     }
+}
 
-    pub fn compute_accel(&self, vel: Vector, track: &Racetrack) -> Vector {
-        let mut mid_iter = self.mid_weights.iter();
-        let mid_out = repeat_array(|| {
-            if let Some(neuron) = mid_iter.next() {
-                let quarter = (neuron.len() - 2) / 4;
-                let mut sum = 0.0;
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Activation, String> {
+        match s {
+            "linear" => Ok(Activation::Linear),
+            "tanh" => Ok(Activation::Tanh),
+            "relu" => Ok(Activation::Relu),
+            "sigmoid" => Ok(Activation::Sigmoid),
+            "step" => Ok(Activation::Step),
+            _ => Err(format!("unknown activation function: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Activation::Linear => "linear",
+            Activation::Tanh => "tanh",
+            Activation::Relu => "relu",
+            Activation::Sigmoid => "sigmoid",
+            Activation::Step => "step",
+        })
+    }
+}
+
+// Ways to combine two parent brains. Uniform crossover picks each weight and
+// bias from either parent, neuron crossover picks each neuron from either
+// parent, and single point crossover takes everything up to some weight from
+// the first parent and the rest from the second.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Crossover {
+    Uniform,
+    Neuron,
+    SinglePoint,
+}
+
+impl FromStr for Crossover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Crossover, String> {
+        match s {
+            "uniform" => Ok(Crossover::Uniform),
+            "neuron" => Ok(Crossover::Neuron),
+            "point" => Ok(Crossover::SinglePoint),
+            _ => Err(format!("unknown crossover: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Crossover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Crossover::Uniform => "uniform",
+            Crossover::Neuron => "neuron",
+            Crossover::SinglePoint => "point",
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct HiddenLayer {
+    pub size: usize,
+    pub activation: Activation,
+}
+
+impl HiddenLayer {
+    pub fn new(size: usize, activation: Activation) -> HiddenLayer {
+        HiddenLayer { size, activation }
+    }
+}
+
+// A fully connected layer. The weights of each neuron are stored one neuron
+// after another. Each neuron also has a bias.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+struct Layer {
+    n_inputs: usize,
+    weights: Vec<f32>,
+    biases: Vec<f32>,
+    activation: Activation,
+}
+
+impl Layer {
+    fn random(n_inputs: usize, n_outputs: usize, activation: Activation, rng: &mut Rng) -> Layer {
+        Layer {
+            n_inputs,
+            weights: iter::repeat_with(|| random_f32(rng))
+                .take(n_inputs * n_outputs)
+                .collect(),
+            biases: iter::repeat_with(|| random_f32(rng))
+                .take(n_outputs)
+                .collect(),
+            activation,
+        }
+    }
+
+    fn read<R: Read>(
+        n_inputs: usize,
+        n_outputs: usize,
+        activation: Activation,
+        has_biases: bool,
+        r: &mut R,
+    ) -> io::Result<Layer> {
+        let mut weights = Vec::new();
+        for _ in 0..n_inputs * n_outputs {
+            weights.push(read_f32(r)?);
+        }
+        let mut biases = Vec::new();
+        for _ in 0..n_outputs {
+            biases.push(if has_biases { read_f32(r)? } else { 0.0 });
+        }
+        Ok(Layer {
+            n_inputs,
+            weights,
+            biases,
+            activation,
+        })
+    }
+
+    fn n_outputs(&self) -> usize {
+        self.biases.len()
+    }
+
+    fn compute(&self, inputs: &[f32]) -> Vec<f32> {
+        self.weights
+            .chunks(self.n_inputs)
+            .zip(self.biases.iter())
+            .map(|(neuron, &bias)| {
+                let sum: f32 = neuron.iter().zip(inputs).map(|(&w, &i)| w * i).sum();
+                self.activation.apply(sum + bias)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Sensors {
+    // Whether each cell is open, for every second cell of the view square.
+    Grid,
+    // The distance to the nearest wall along this many rays spread evenly
+    // around the racer, starting due east and going counterclockwise.
+    Rays(usize),
+}
+
+impl Sensors {
+    fn view_dist(self, view_dist: i32) -> i32 {
+        let view_dist = i32::max(1, i32::abs(view_dist));
+        match self {
+            Sensors::Grid => pos_round_up_2(view_dist),
+            Sensors::Rays(_) => view_dist,
+        }
+    }
+
+    // Includes the two velocity inputs.
+    fn n_inputs(self, view_dist: i32) -> usize {
+        match self {
+            Sensors::Grid => ((view_dist / 2) * (view_dist / 2) * 4) as usize - 2,
+            Sensors::Rays(n_rays) => n_rays + 2,
+        }
+    }
+
+    fn sense(self, view_dist: i32, vel: Vector, track: &Racetrack) -> Vec<f32> {
+        let n_inputs = self.n_inputs(view_dist);
+        let mut values = vec![0.0; n_inputs];
+        match self {
+            Sensors::Grid => {
+                let quarter = (n_inputs - 2) / 4;
                 let mut i = 0;
-                for x in (1..self.view_dist).step_by(2) {
-                    for y in (1..self.view_dist).step_by(2) {
+                for x in (1..view_dist).step_by(2) {
+                    for y in (1..view_dist).step_by(2) {
                         if let Some(true) = track.get(Vector::new(x, y)) {
-                            sum += neuron[i];
+                            values[i] += 1.0;
                         }
                         if let Some(true) = track.get(Vector::new(-x, y)) {
-                            sum += neuron[i + 1 * quarter];
+                            values[i + quarter] += 1.0;
                         }
                         if let Some(true) = track.get(Vector::new(-x, -y)) {
-                            sum += neuron[i + 2 * quarter];
+                            values[i + 2 * quarter] += 1.0;
                         }
                         if let Some(true) = track.get(Vector::new(x, -y)) {
-                            sum += neuron[i + 3 * quarter];
+                            values[i + 3 * quarter] += 1.0;
                         }
                         i += 1;
                     }
                 }
-                sum += vel.x as f32 * neuron[neuron.len() - 2];
-                sum += vel.y as f32 * neuron[neuron.len() - 1];
-                sum
-            } else {
-                unreachable!()
             }
-        });
-        assert!(mid_iter.next().is_none());
-        let mut out = vec![
-            compute_out(&self.out_weights[0], &mid_out),
-            compute_out(&self.out_weights[1], &mid_out),
-            compute_out(&self.out_weights[2], &mid_out),
-            compute_out(&self.out_weights[3], &mid_out),
-            compute_out(&self.out_weights[4], &mid_out),
-        ]
-        .into_iter();
+            Sensors::Rays(n_rays) => {
+                for (i, value) in values[..n_rays].iter_mut().enumerate() {
+                    let angle = i as f32 / n_rays as f32 * 2.0 * std::f32::consts::PI;
+                    *value = cast_ray(angle.cos(), angle.sin(), view_dist, track);
+                }
+            }
+        }
+        values[n_inputs - 2] += vel.x as f32;
+        values[n_inputs - 1] += vel.y as f32;
+        values
+    }
+
+    fn code(self) -> (u8, u32) {
+        match self {
+            Sensors::Grid => (0, 0),
+            Sensors::Rays(n_rays) => (1, n_rays as u32),
+        }
+    }
+
+    fn from_code(code: u8, n_rays: u32) -> Option<Sensors> {
+        match (code, n_rays) {
+            (0, 0) => Some(Sensors::Grid),
+            (1, n_rays) if n_rays > 0 => Some(Sensors::Rays(n_rays as usize)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Sensors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sensors, String> {
+        if s == "grid" {
+            return Ok(Sensors::Grid);
+        } else if let Some(n_rays) = s.strip_prefix("rays:") {
+            if let Ok(n_rays) = usize::from_str(n_rays) {
+                if n_rays > 0 {
+                    return Ok(Sensors::Rays(n_rays));
+                }
+            }
+        }
+        Err(format!("invalid sensors: {}", s))
+    }
+}
+
+impl fmt::Display for Sensors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sensors::Grid => write!(f, "grid"),
+            Sensors::Rays(n_rays) => write!(f, "rays:{}", n_rays),
+        }
+    }
+}
+
+// The accelerations a brain chooses from, with one output neuron for each.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Actions {
+    // One step east, north, west, or south, or coasting.
+    Cardinal,
+    // Changing each velocity component by at most this much. A reach of 1
+    // gives the nine choices of the paper game.
+    Moore(u32),
+    // The cardinal actions as brains saved in version 1 were trained to choose
+    // them. The first two outputs both step east, each later one steps the way
+    // of the output before it in Cardinal, and none coasts.
+    ShiftedCardinal,
+}
+
+impl Actions {
+    pub fn count(self) -> usize {
+        match self {
+            Actions::Cardinal | Actions::ShiftedCardinal => 5,
+            Actions::Moore(reach) => ((reach * 2 + 1) * (reach * 2 + 1)) as usize,
+        }
+    }
+
+    // The acceleration for the output neuron with the given index.
+    pub fn accel(self, i: usize) -> Vector {
+        match self {
+            Actions::Cardinal => match i {
+                0 => Vector::new(1, 0),
+                1 => Vector::new(0, 1),
+                2 => Vector::new(-1, 0),
+                3 => Vector::new(0, -1),
+                4 => Vector::ORIGIN,
+                _ => panic!("no action {}", i),
+            },
+            Actions::Moore(reach) => {
+                assert!(i < self.count(), "no action {}", i);
+                let width = reach as usize * 2 + 1;
+                let reach = reach as i32;
+                Vector::new((i % width) as i32 - reach, (i / width) as i32 - reach)
+            }
+            Actions::ShiftedCardinal => {
+                assert!(i < self.count(), "no action {}", i);
+                Actions::Cardinal.accel(usize::max(i, 1) - 1)
+            }
+        }
+    }
+
+    fn output_layer(self) -> HiddenLayer {
+        HiddenLayer::new(self.count(), Activation::Linear)
+    }
+
+    fn code(self) -> (u8, u32) {
+        match self {
+            Actions::Cardinal => (0, 0),
+            Actions::Moore(reach) => (1, reach),
+            Actions::ShiftedCardinal => (2, 0),
+        }
+    }
+
+    fn from_code(code: u8, reach: u32) -> Option<Actions> {
+        match (code, reach) {
+            (0, 0) => Some(Actions::Cardinal),
+            (1, reach) if reach > 0 && reach <= MAX_REACH => Some(Actions::Moore(reach)),
+            (2, 0) => Some(Actions::ShiftedCardinal),
+            _ => None,
+        }
+    }
+}
+
+// Bounds the number of outputs.
+const MAX_REACH: u32 = 16;
+
+impl FromStr for Actions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Actions, String> {
+        if s == "cardinal" {
+            return Ok(Actions::Cardinal);
+        } else if s == "moore" {
+            return Ok(Actions::Moore(1));
+        } else if let Some(reach) = s.strip_prefix("moore:") {
+            if let Ok(reach) = u32::from_str(reach) {
+                if reach > 0 && reach <= MAX_REACH {
+                    return Ok(Actions::Moore(reach));
+                }
+            }
+        }
+        Err(format!("invalid actions: {}", s))
+    }
+}
+
+impl fmt::Display for Actions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Actions::Cardinal => write!(f, "cardinal"),
+            Actions::Moore(1) => write!(f, "moore"),
+            Actions::Moore(reach) => write!(f, "moore:{}", reach),
+            Actions::ShiftedCardinal => write!(f, "cardinal (version 1)"),
+        }
+    }
+}
+
+// The returned distance is a fraction of the view distance. It is 1 if no wall
+// is in view along the ray.
+fn cast_ray(dir_x: f32, dir_y: f32, view_dist: i32, track: &Racetrack) -> f32 {
+    let steps = view_dist * RAY_STEPS_PER_CELL;
+    for step in 1..=steps {
+        let dist = step as f32 / RAY_STEPS_PER_CELL as f32;
+        let pt = Vector::new((dir_x * dist).round() as i32, (dir_y * dist).round() as i32);
+        if let Some(false) = track.get(pt) {
+            return dist / view_dist as f32;
+        }
+    }
+    1.0
+}
+
+const RAY_STEPS_PER_CELL: i32 = 4;
+
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct BrainBuilder {
+    view_dist: Option<i32>,
+    sensors: Option<Sensors>,
+    actions: Option<Actions>,
+    hidden_layers: Option<Vec<HiddenLayer>>,
+}
+
+impl BrainBuilder {
+    fn new() -> BrainBuilder {
+        BrainBuilder {
+            view_dist: None,
+            sensors: None,
+            actions: None,
+            hidden_layers: None,
+        }
+    }
+
+    pub fn view_dist(mut self, view_dist: i32) -> BrainBuilder {
+        self.view_dist = Some(view_dist);
+        self
+    }
+
+    pub fn sensors(mut self, sensors: Sensors) -> BrainBuilder {
+        self.sensors = Some(sensors);
+        self
+    }
+
+    pub fn actions(mut self, actions: Actions) -> BrainBuilder {
+        self.actions = Some(actions);
+        self
+    }
+
+    // The hidden layer sizes must all be positive.
+    pub fn hidden_layers(mut self, hidden_layers: Vec<HiddenLayer>) -> BrainBuilder {
+        self.hidden_layers = Some(hidden_layers);
+        self
+    }
+
+    pub fn random(self, rng: &mut Rng) -> Brain {
+        Brain::random(self, rng)
+    }
+}
+
+impl Brain {
+    pub fn builder() -> BrainBuilder {
+        BrainBuilder::new()
+    }
+
+    fn random(builder: BrainBuilder, rng: &mut Rng) -> Brain {
+        let sensors = builder.sensors.unwrap_or(Sensors::Grid);
+        let view_dist = sensors.view_dist(builder.view_dist.unwrap_or(20));
+        let actions = builder.actions.unwrap_or(Actions::Cardinal);
+        let hidden_layers = builder
+            .hidden_layers
+            .unwrap_or_else(|| vec![HiddenLayer::new(32, Activation::Tanh)]);
+        let mut n_inputs = sensors.n_inputs(view_dist);
+        let mut layers = Vec::with_capacity(hidden_layers.len() + 1);
+        for hidden in hidden_layers
+            .iter()
+            .chain(iter::once(&actions.output_layer()))
+        {
+            assert!(hidden.size > 0, "empty brain layer");
+            layers.push(Layer::random(n_inputs, hidden.size, hidden.activation, rng));
+            n_inputs = hidden.size;
+        }
+        Brain {
+            view_dist,
+            sensors,
+            actions,
+            layers,
+        }
+    }
+
+    pub fn mutant(&self, rng: &mut Rng, amount: f64) -> Brain {
+        let amount = amount as f32;
+        let mut mutant = self.clone();
+        for layer in mutant.layers.iter_mut() {
+            for weight in layer.weights.iter_mut().chain(layer.biases.iter_mut()) {
+                *weight += random_f32(rng) * amount;
+            }
+        }
+        mutant
+    }
+
+    // The parents must have the same shape, meaning the same view distance,
+    // sensors, and hidden layers.
+    pub fn crossover(a: &Brain, b: &Brain, rng: &mut Rng, kind: Crossover) -> Brain {
+        assert!(a.same_shape(b), "crossover of brains with different shapes");
+        let mut child = a.clone();
+        match kind {
+            Crossover::Uniform => {
+                for (child_layer, b_layer) in child.layers.iter_mut().zip(b.layers.iter()) {
+                    let child_params = child_layer
+                        .weights
+                        .iter_mut()
+                        .chain(child_layer.biases.iter_mut());
+                    let b_params = b_layer.weights.iter().chain(b_layer.biases.iter());
+                    for (param, &b_param) in child_params.zip(b_params) {
+                        if random_bool(rng) {
+                            *param = b_param;
+                        }
+                    }
+                }
+            }
+            Crossover::Neuron => {
+                for (child_layer, b_layer) in child.layers.iter_mut().zip(b.layers.iter()) {
+                    let n_inputs = child_layer.n_inputs;
+                    let child_neurons = child_layer
+                        .weights
+                        .chunks_mut(n_inputs)
+                        .zip(child_layer.biases.iter_mut());
+                    let b_neurons = b_layer.weights.chunks(n_inputs).zip(b_layer.biases.iter());
+                    for ((weights, bias), (b_weights, &b_bias)) in child_neurons.zip(b_neurons) {
+                        if random_bool(rng) {
+                            weights.copy_from_slice(b_weights);
+                            *bias = b_bias;
+                        }
+                    }
+                }
+            }
+            Crossover::SinglePoint => {
+                let n_params = a
+                    .layers
+                    .iter()
+                    .map(|layer| layer.weights.len() + layer.biases.len())
+                    .sum::<usize>();
+                // The parameters from the point on come from b.
+                let point = rng.below(n_params as u64 + 1) as usize;
+                let child_params = child
+                    .layers
+                    .iter_mut()
+                    .flat_map(|layer| layer.weights.iter_mut().chain(layer.biases.iter_mut()));
+                let b_params = b
+                    .layers
+                    .iter()
+                    .flat_map(|layer| layer.weights.iter().chain(layer.biases.iter()));
+                for (param, &b_param) in child_params.zip(b_params).skip(point) {
+                    *param = b_param;
+                }
+            }
+        }
+        child
+    }
+
+    // The Euclidean distance between the weights and biases of two brains with
+    // the same shape.
+    pub fn distance(&self, other: &Brain) -> f64 {
+        assert!(
+            self.same_shape(other),
+            "distance between brains with different shapes"
+        );
+        self.layers
+            .iter()
+            .zip(other.layers.iter())
+            .flat_map(|(a, b)| {
+                let a_params = a.weights.iter().chain(a.biases.iter());
+                let b_params = b.weights.iter().chain(b.biases.iter());
+                a_params.zip(b_params)
+            })
+            .map(|(&a, &b)| {
+                let d = (a - b) as f64;
+                d * d
+            })
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    pub fn same_shape(&self, other: &Brain) -> bool {
+        self.view_dist == other.view_dist
+            && self.sensors == other.sensors
+            && self.actions == other.actions
+            && self.hidden_layers() == other.hidden_layers()
+    }
+
+    pub fn view_dist(&self) -> i32 {
+        self.view_dist
+    }
+
+    pub fn sensors(&self) -> Sensors {
+        self.sensors
+    }
+
+    pub fn actions(&self) -> Actions {
+        self.actions
+    }
+
+    pub fn hidden_layers(&self) -> Vec<HiddenLayer> {
+        self.layers[..self.layers.len() - 1]
+            .iter()
+            .map(|layer| HiddenLayer::new(layer.n_outputs(), layer.activation))
+            .collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Brain, LoadError> {
+        Brain::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        write_i32(w, self.view_dist)?;
+        let (sensors, n_rays) = self.sensors.code();
+        write_u8(w, sensors)?;
+        write_u32(w, n_rays)?;
+        let (actions, reach) = self.actions.code();
+        write_u8(w, actions)?;
+        write_u32(w, reach)?;
+        write_u32(w, self.layers[0].n_inputs as u32)?;
+        let hidden_layers = self.hidden_layers();
+        write_u32(w, hidden_layers.len() as u32)?;
+        for hidden in hidden_layers.iter() {
+            write_u32(w, hidden.size as u32)?;
+            write_u8(w, hidden.activation.code())?;
+        }
+        for layer in self.layers.iter() {
+            for &weight in layer.weights.iter().chain(layer.biases.iter()) {
+                write_f32(w, weight)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Brain, LoadError> {
+        let brain = Brain::decode(r)?;
+        if !at_end(r)? {
+            return Err(LoadError::TrailingData);
+        }
+        Ok(brain)
+    }
+
+    // Like read_from, but allows more data to follow the brain.
+    pub(crate) fn decode<R: Read>(r: &mut R) -> Result<Brain, LoadError> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = read_u32(r)?;
+        if version == 0 || version > VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let view_dist = read_i32(r)?;
+        let sensors = if version >= 4 {
+            let code = read_u8(r)?;
+            let n_rays = read_u32(r)?;
+            Sensors::from_code(code, n_rays).ok_or(LoadError::BadSensors(code, n_rays))?
+        } else {
+            Sensors::Grid
+        };
+        let actions = if version >= 5 {
+            let code = read_u8(r)?;
+            let reach = read_u32(r)?;
+            Actions::from_code(code, reach).ok_or(LoadError::BadActions(code, reach))?
+        } else if version == 1 {
+            Actions::ShiftedCardinal
+        } else {
+            Actions::Cardinal
+        };
+        if view_dist <= 0 || view_dist != sensors.view_dist(view_dist) {
+            return Err(LoadError::BadViewDist(view_dist));
+        }
+        let n_inputs = sensors.n_inputs(view_dist);
+        // Version 1 brains always have one hidden layer.
+        let n_mid = if version == 1 { read_u32(r)? } else { 0 };
+        let found_inputs = read_u32(r)? as usize;
+        if found_inputs != n_inputs {
+            return Err(LoadError::WeightCount {
+                what: "inputs",
+                expected: n_inputs,
+                found: found_inputs,
+            });
+        }
+        let hidden_layers = if version == 1 {
+            vec![HiddenLayer::new(n_mid as usize, Activation::Linear)]
+        } else {
+            let n_hidden = read_u32(r)?;
+            let mut hidden_layers = Vec::new();
+            for _ in 0..n_hidden {
+                let size = read_u32(r)? as usize;
+                let activation = if version >= 3 {
+                    let code = read_u8(r)?;
+                    Activation::from_code(code).ok_or(LoadError::BadActivation(code))?
+                } else {
+                    Activation::Linear
+                };
+                hidden_layers.push(HiddenLayer::new(size, activation));
+            }
+            hidden_layers
+        };
+        if hidden_layers.iter().any(|hidden| hidden.size == 0) {
+            return Err(LoadError::EmptyLayer);
+        }
+        let has_biases = version >= 3;
+        let mut n_inputs = n_inputs;
+        let mut layers = Vec::with_capacity(hidden_layers.len() + 1);
+        for hidden in hidden_layers
+            .iter()
+            .chain(iter::once(&actions.output_layer()))
+        {
+            layers.push(Layer::read(
+                n_inputs,
+                hidden.size,
+                hidden.activation,
+                has_biases,
+                r,
+            )?);
+            n_inputs = hidden.size;
+        }
+        Ok(Brain {
+            view_dist,
+            sensors,
+            actions,
+            layers,
+        })
+    }
+
+    pub fn compute_accel(&self, vel: Vector, track: &Racetrack) -> Vector {
+        let mut values = self.sensors.sense(self.view_dist, vel, track);
+        for layer in self.layers.iter() {
+            values = layer.compute(&values);
+        }
+        let mut out = values.into_iter();
         let mut max = out.next().unwrap();
         let mut max_i = 0;
         for (i, choice) in out.enumerate() {
             if choice > max {
                 max = choice;
-                max_i = i;
+                max_i = i + 1;
             }
         }
-        match max_i {
-            0 => Vector::new(1, 0),
-            1 => Vector::new(0, 1),
-            2 => Vector::new(-1, 0),
-            3 => Vector::new(0, -1),
-            4 => Vector::ORIGIN,
-            _ => unreachable!(),
+        self.actions.accel(max_i)
+    }
+}
+
+// Brain file format, all numbers little-endian:
+//   magic        8 bytes, "VECRACBR"
+//   version      u32, currently 5
+//   view_dist    i32, positive, and even for grid sensors
+//   sensors      u8, 0 for grid sensors or 1 for ray sensors
+//   n_rays       u32, number of rays for ray sensors, otherwise 0
+//   actions      u8, 0 for the 5 cardinal actions, 1 for the Moore
+//                neighborhood, or 2 for the cardinal actions of version 1
+//   reach        u32, reach of the Moore neighborhood from 1 to 16, otherwise 0
+//   n_inputs     u32, number of inputs to the first layer, determined by
+//                view_dist and the sensors
+//   n_hidden     u32, number of hidden layers
+//   hidden       n_hidden times a u32 number of neurons in a hidden layer and a
+//                u8 activation function: 0 linear, 1 tanh, 2 relu, 3 sigmoid,
+//                or 4 step
+//   layers       each layer in order, ending with a linear output neuron for
+//                each action:
+//                f32 weights one neuron after another, then an f32 bias for
+//                each neuron
+// Nothing may follow the last bias.
+//
+// Version 4 had no actions or reach, and always used the cardinal actions, as
+// version 1 did with its outputs shifted by one.
+// Version 3 had no sensors or n_rays, and always used grid sensors.
+// Version 2 had no activation functions or biases, and so was linear.
+// Version 1 had exactly one hidden layer. In place of n_inputs, n_hidden and
+// hidden, it had u32 numbers of hidden neurons and of inputs per hidden neuron.
+const MAGIC: &[u8; 8] = b"VECRACBR";
+const VERSION: u32 = 5;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u32),
+    BadViewDist(i32),
+    WeightCount {
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    EmptyLayer,
+    BadActivation(u8),
+    BadSensors(u8, u32),
+    BadActions(u8, u32),
+    TrailingData,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Truncated => write!(f, "brain file is truncated"),
+            LoadError::BadMagic => write!(f, "not a brain file"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported brain file version {}", version)
+            }
+            LoadError::BadViewDist(view_dist) => {
+                write!(f, "invalid view distance {} in brain file", view_dist)
+            }
+            LoadError::WeightCount {
+                what,
+                expected,
+                found,
+            } => write!(
+                f,
+                "brain file has {} {}, but {} were expected",
+                found, what, expected
+            ),
+            LoadError::EmptyLayer => write!(f, "brain file has a layer with no neurons"),
+            LoadError::BadActivation(code) => {
+                write!(f, "brain file has unknown activation function {}", code)
+            }
+            LoadError::BadSensors(code, n_rays) => write!(
+                f,
+                "brain file has unknown sensors {} with {} rays",
+                code, n_rays
+            ),
+            LoadError::BadActions(code, reach) => write!(
+                f,
+                "brain file has unknown actions {} with reach {}",
+                code, reach
+            ),
+            LoadError::TrailingData => write!(f, "brain file has trailing data"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            LoadError::Truncated
+        } else {
+            LoadError::Io(err)
         }
     }
 }
@@ -117,44 +884,360 @@ fn random_f32(rng: &mut Rng) -> f32 {
     rng.forward() as f32 / Rng::RAND_MAX as f32 * 2.0 - 1.0
 }
 
-fn repeat_array<T, F: FnMut() -> T>(mut f: F) -> [T; N_MID_WEIGHTS] {
-    // TODO: This is dumb.
-    [
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-        f(),
-    ]
-}
-
-fn compute_out(neuron: &[f32; N_MID_WEIGHTS], inputs: &[f32; N_MID_WEIGHTS]) -> f32 {
-    neuron.into_iter().zip(inputs).map(|(&w, &i)| w * i).sum()
+// The high bits of the RNG are the most random.
+fn random_bool(rng: &mut Rng) -> bool {
+    rng.forward() > Rng::RAND_MAX / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tanh_layers(sizes: &[usize]) -> Vec<HiddenLayer> {
+        sizes
+            .iter()
+            .map(|&size| HiddenLayer::new(size, Activation::Tanh))
+            .collect()
+    }
+
+    fn random_brain(view_dist: i32, hidden_layers: Vec<HiddenLayer>, rng: &mut Rng) -> Brain {
+        Brain::builder()
+            .view_dist(view_dist)
+            .hidden_layers(hidden_layers)
+            .random(rng)
+    }
+
+    fn encode(brain: &Brain) -> Vec<u8> {
+        let mut buf = Vec::new();
+        brain.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::with_seed(99);
+        let brain = random_brain(10, tanh_layers(&[32]), &mut rng);
+        let buf = encode(&brain);
+        let loaded = Brain::read_from(&mut &buf[..]).unwrap();
+        assert!(loaded == brain);
+        assert_eq!(encode(&loaded), buf);
+    }
+
+    #[test]
+    fn round_trip_no_hidden_layers() {
+        let mut rng = Rng::with_seed(12);
+        let brain = random_brain(8, tanh_layers(&[]), &mut rng);
+        assert_eq!(brain.hidden_layers(), Vec::new());
+        let buf = encode(&brain);
+        assert!(Brain::read_from(&mut &buf[..]).unwrap() == brain);
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let mut rng = Rng::with_seed(4);
+        let linear = [HiddenLayer::new(32, Activation::Linear)];
+        let mut brain = random_brain(4, linear.to_vec(), &mut rng);
+        brain.actions = Actions::ShiftedCardinal;
+        for layer in brain.layers.iter_mut() {
+            for bias in layer.biases.iter_mut() {
+                *bias = 0.0;
+            }
+        }
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        write_u32(&mut buf, 1).unwrap();
+        write_i32(&mut buf, 4).unwrap();
+        write_u32(&mut buf, 32).unwrap();
+        write_u32(&mut buf, Sensors::Grid.n_inputs(4) as u32).unwrap();
+        for layer in brain.layers.iter() {
+            for &weight in layer.weights.iter() {
+                write_f32(&mut buf, weight).unwrap();
+            }
+        }
+        let loaded = Brain::read_from(&mut &buf[..]).unwrap();
+        assert!(loaded == brain);
+        // It still drives as it was trained to after saving it again.
+        assert!(Brain::read_from(&mut &encode(&loaded)[..]).unwrap() == brain);
+    }
+
+    #[test]
+    fn version_1_outputs_are_shifted() {
+        let accels = (0..5)
+            .map(|i| Actions::ShiftedCardinal.accel(i))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            accels,
+            vec![
+                Vector::new(1, 0),
+                Vector::new(1, 0),
+                Vector::new(0, 1),
+                Vector::new(-1, 0),
+                Vector::new(0, -1),
+            ]
+        );
+    }
+
+    #[test]
+    fn any_shape_drives() {
+        let mut rng = Rng::with_seed(8);
+        let mut track = Racetrack::builder().view_dist(6).seed(8).build();
+        track.translate(Vector::ORIGIN);
+        for shape in [&[][..], &[1][..], &[16, 4][..], &[3, 3, 3][..]].iter() {
+            let brain = random_brain(6, tanh_layers(shape), &mut rng).mutant(&mut rng, 0.5);
+            let accel = brain.compute_accel(Vector::new(1, -2), &track);
+            assert!(i32::abs(accel.x) + i32::abs(accel.y) <= 1);
+        }
+    }
+
+    #[test]
+    fn round_trip_rays() {
+        let mut rng = Rng::with_seed(31);
+        let brain = Brain::builder()
+            .view_dist(7)
+            .sensors(Sensors::Rays(12))
+            .random(&mut rng);
+        assert_eq!(brain.view_dist(), 7);
+        let buf = encode(&brain);
+        assert!(Brain::read_from(&mut &buf[..]).unwrap() == brain);
+    }
+
+    #[test]
+    fn moore_actions() {
+        let mut rng = Rng::with_seed(40);
+        let mut track = Racetrack::builder().view_dist(6).seed(2).build();
+        track.translate(Vector::ORIGIN);
+        for &actions in [Actions::Moore(1), Actions::Moore(3)].iter() {
+            let accels = (0..actions.count())
+                .map(|i| actions.accel(i))
+                .collect::<Vec<_>>();
+            let reach = if let Actions::Moore(reach) = actions {
+                reach as i32
+            } else {
+                unreachable!()
+            };
+            assert_eq!(accels.len(), ((reach * 2 + 1) * (reach * 2 + 1)) as usize);
+            for y in -reach..=reach {
+                for x in -reach..=reach {
+                    assert!(accels.contains(&Vector::new(x, y)));
+                }
+            }
+            let brain = Brain::builder()
+                .view_dist(6)
+                .actions(actions)
+                .random(&mut rng);
+            assert_eq!(brain.actions(), actions);
+            let buf = encode(&brain);
+            assert!(Brain::read_from(&mut &buf[..]).unwrap() == brain);
+            let accel = brain.compute_accel(Vector::new(1, 1), &track);
+            assert!(accels.contains(&accel));
+        }
+        assert_eq!("moore".parse(), Ok(Actions::Moore(1)));
+        assert_eq!("moore:2".parse(), Ok(Actions::Moore(2)));
+        assert_eq!("cardinal".parse(), Ok(Actions::Cardinal));
+        assert_eq!(Actions::Moore(1).to_string(), "moore");
+        assert_eq!(Actions::Moore(4).to_string(), "moore:4");
+        assert!("moore:0".parse::<Actions>().is_err());
+    }
+
+    #[test]
+    fn loads_version_4() {
+        let mut rng = Rng::with_seed(3);
+        let brain = random_brain(4, tanh_layers(&[5]), &mut rng);
+        let buf = encode(&brain);
+        // Version 4 had nothing between n_rays and n_inputs.
+        let mut old = buf[..8].to_vec();
+        write_u32(&mut old, 4).unwrap();
+        old.extend_from_slice(&buf[12..21]);
+        old.extend_from_slice(&buf[26..]);
+        let loaded = Brain::read_from(&mut &old[..]).unwrap();
+        assert_eq!(loaded.actions(), Actions::Cardinal);
+        assert!(loaded == brain);
+    }
+
+    #[test]
+    fn rays_measure_walls() {
+        let mut track = Racetrack::builder().view_dist(10).seed(3).build();
+        track.translate(Vector::ORIGIN);
+        let values = Sensors::Rays(8).sense(10, Vector::new(2, -1), &track);
+        assert_eq!(values.len(), 10);
+        assert!(values[..8].iter().any(|&dist| dist > 0.25));
+        for (i, &dist) in values[..8].iter().enumerate() {
+            assert!(dist > 0.0 && dist <= 1.0);
+            let angle = i as f32 / 8.0 * 2.0 * std::f32::consts::PI;
+            let reach = dist * 10.0;
+            let pt = Vector::new(
+                (angle.cos() * reach).round() as i32,
+                (angle.sin() * reach).round() as i32,
+            );
+            if dist < 1.0 {
+                assert_eq!(track.get(pt), Some(false));
+            }
+        }
+        assert_eq!(&values[8..], &[2.0, -1.0]);
+        assert_eq!("rays:8".parse(), Ok(Sensors::Rays(8)));
+        assert_eq!(Sensors::Rays(8).to_string(), "rays:8");
+        assert!("rays:0".parse::<Sensors>().is_err());
+    }
+
+    fn params(brain: &Brain) -> Vec<f32> {
+        brain
+            .layers
+            .iter()
+            .flat_map(|layer| layer.weights.iter().chain(layer.biases.iter()))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn crossover_mixes_parents() {
+        let mut rng = Rng::with_seed(21);
+        let a = random_brain(4, tanh_layers(&[6, 3]), &mut rng);
+        let b = random_brain(4, tanh_layers(&[6, 3]), &mut rng);
+        let (a_params, b_params) = (params(&a), params(&b));
+        for &kind in [
+            Crossover::Uniform,
+            Crossover::Neuron,
+            Crossover::SinglePoint,
+        ]
+        .iter()
+        {
+            let child = Brain::crossover(&a, &b, &mut rng, kind);
+            assert!(child.same_shape(&a));
+            let child_params = params(&child);
+            for (i, param) in child_params.iter().enumerate() {
+                assert!(*param == a_params[i] || *param == b_params[i]);
+            }
+            if kind == Crossover::SinglePoint {
+                let point = (0..child_params.len())
+                    .find(|&i| child_params[i] != a_params[i])
+                    .unwrap_or(child_params.len());
+                assert_eq!(child_params[point..], b_params[point..]);
+            }
+        }
+        let child = Brain::crossover(&a, &b, &mut rng, Crossover::Neuron);
+        for (layer, a_layer) in child.layers.iter().zip(a.layers.iter()) {
+            let from_a = layer
+                .weights
+                .chunks(layer.n_inputs)
+                .zip(a_layer.weights.chunks(layer.n_inputs))
+                .zip(layer.biases.iter().zip(a_layer.biases.iter()))
+                .map(|((w, a_w), (bias, a_bias))| (w == a_w, bias == a_bias));
+            for (weights_from_a, bias_from_a) in from_a {
+                assert_eq!(weights_from_a, bias_from_a);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn crossover_needs_same_shape() {
+        let mut rng = Rng::with_seed(21);
+        let a = random_brain(4, tanh_layers(&[6]), &mut rng);
+        let b = random_brain(4, tanh_layers(&[5]), &mut rng);
+        Brain::crossover(&a, &b, &mut rng, Crossover::Uniform);
+    }
+
+    #[test]
+    fn activations() {
+        let xs = [-2.0, 0.0, 0.5];
+        let apply =
+            |activation: Activation| xs.iter().map(|&x| activation.apply(x)).collect::<Vec<_>>();
+        assert_eq!(apply(Activation::Linear), vec![-2.0, 0.0, 0.5]);
+        assert_eq!(apply(Activation::Relu), vec![0.0, 0.0, 0.5]);
+        assert_eq!(apply(Activation::Step), vec![0.0, 0.0, 1.0]);
+        assert_eq!(apply(Activation::Sigmoid)[1], 0.5);
+        assert_eq!(apply(Activation::Tanh)[1], 0.0);
+        for code in 0..5 {
+            let activation = Activation::from_code(code).unwrap();
+            assert_eq!(activation.code(), code);
+            assert_eq!(activation.to_string().parse(), Ok(activation));
+        }
+    }
+
+    #[test]
+    fn distance() {
+        let mut rng = Rng::with_seed(4);
+        let brain = random_brain(3, tanh_layers(&[2]), &mut rng);
+        let mut other = brain.clone();
+        assert_eq!(brain.distance(&other), 0.0);
+        other.layers[0].weights[1] += 3.0;
+        other.layers[1].biases[4] -= 4.0;
+        assert!((brain.distance(&other) - 5.0).abs() < 1e-5);
+        assert_eq!(brain.distance(&other), other.distance(&brain));
+    }
+
+    #[test]
+    fn mutant_changes_biases() {
+        let mut rng = Rng::with_seed(2);
+        let brain = random_brain(4, tanh_layers(&[3]), &mut rng);
+        let mutant = brain.mutant(&mut rng, 0.5);
+        for (a, b) in brain.layers.iter().zip(mutant.layers.iter()) {
+            assert!(a.biases != b.biases);
+        }
+    }
+
+    #[test]
+    fn round_trip_file() {
+        let mut rng = Rng::with_seed(5);
+        let brain = random_brain(6, tanh_layers(&[7, 3, 9]), &mut rng).mutant(&mut rng, 0.1);
+        let path = std::env::temp_dir().join(format!("vec-rac-test-{}.brain", std::process::id()));
+        brain.save(&path).unwrap();
+        let loaded = Brain::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap() == brain);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut rng = Rng::with_seed(1);
+        let buf = encode(&random_brain(4, tanh_layers(&[8]), &mut rng));
+        for len in [0, 5, 12, 20, buf.len() - 1].iter() {
+            match Brain::read_from(&mut &buf[..*len]) {
+                Err(LoadError::Truncated) => (),
+                other => panic!("length {}: {:?}", len, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut rng = Rng::with_seed(1);
+        let buf = encode(&random_brain(4, tanh_layers(&[8]), &mut rng));
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            Brain::read_from(&mut &bad_magic[..]),
+            Err(LoadError::BadMagic)
+        ));
+        let mut bad_version = buf.clone();
+        bad_version[8] = 99;
+        assert!(matches!(
+            Brain::read_from(&mut &bad_version[..]),
+            Err(LoadError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn mismatched_shape() {
+        let mut rng = Rng::with_seed(1);
+        let buf = encode(&random_brain(4, tanh_layers(&[8]), &mut rng));
+        let mut bad_view = buf.clone();
+        bad_view[12] = 6;
+        assert!(matches!(
+            Brain::read_from(&mut &bad_view[..]),
+            Err(LoadError::WeightCount { what: "inputs", .. })
+        ));
+        let mut odd_view = buf.clone();
+        odd_view[12] = 5;
+        assert!(matches!(
+            Brain::read_from(&mut &odd_view[..]),
+            Err(LoadError::BadViewDist(5))
+        ));
+        let mut extra = buf.clone();
+        extra.push(0);
+        assert!(matches!(
+            Brain::read_from(&mut &extra[..]),
+            Err(LoadError::TrailingData)
+        ));
+    }
 }
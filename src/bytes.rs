@@ -0,0 +1,79 @@
+use std::io::{self, Read, Write};
+
+// Little-endian encoding helpers shared by the file formats.
+
+pub(crate) fn write_u8<W: Write>(w: &mut W, n: u8) -> io::Result<()> {
+    w.write_all(&[n])
+}
+
+pub(crate) fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub(crate) fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub(crate) fn write_i32<W: Write>(w: &mut W, n: i32) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub(crate) fn write_f32<W: Write>(w: &mut W, n: f32) -> io::Result<()> {
+    write_u32(w, n.to_bits())
+}
+
+pub(crate) fn write_f64<W: Write>(w: &mut W, n: f64) -> io::Result<()> {
+    write_u64(w, n.to_bits())
+}
+
+pub(crate) fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_u32(w, s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    read_u32(r).map(f32::from_bits)
+}
+
+pub(crate) fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    read_u64(r).map(f64::from_bits)
+}
+
+// Returns None if the bytes are not UTF-8.
+pub(crate) fn read_str<R: Read>(r: &mut R) -> io::Result<Option<String>> {
+    let len = read_u32(r)? as usize;
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8(buf).ok())
+}
+
+pub(crate) fn at_end<R: Read>(r: &mut R) -> io::Result<bool> {
+    Ok(r.read(&mut [0u8])? == 0)
+}
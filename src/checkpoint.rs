@@ -0,0 +1,321 @@
+use crate::brain::{self, Brain};
+use crate::bytes::{
+    at_end, read_f64, read_i32, read_str, read_u32, read_u64, read_u8, write_f64, write_str,
+    write_u32, write_u64, write_u8,
+};
+use crate::fitness::Fitness;
+use crate::rng::Rng;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// Checkpoint file format, all numbers little-endian:
+//   magic          8 bytes, "VECRACCP"
+//   version        u32, currently 4
+//   n_args         u32, number of command line arguments
+//   args           n_args strings, each a u32 byte length and then UTF-8
+//   generation     u64, number of generations already run
+//   rng_state      u64, state of the mutation RNG
+//   best_score     f64, score of the best racer so far, or 0 if there is none
+//   best_time      f64, its time in ticks, or 0
+//   best_finished  u8, 1 if it finished every race, otherwise 0
+//   improved_at    u64, generation after the last that found a new best racer
+//   has_best       u8, 1 if a best brain follows, otherwise 0
+//   best           brain in the brain file format, if present
+//   n_brains       u32, population size
+//   brains         n_brains brains in the brain file format
+// Nothing may follow the last brain.
+//
+// Versions 1 to 3 had only the best score, in place of best_score, best_time,
+// and best_finished, and it was an i32 in version 1. A best racer loaded from
+// them is only beaten by a higher score, as it was when they were saved.
+// Versions 1 and 2 had no improved_at, so stagnation is counted from the saved
+// generation when loading them.
+const MAGIC: &[u8; 8] = b"VECRACCP";
+const VERSION: u32 = 4;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Checkpoint {
+    pub args: Vec<String>,
+    pub generation: u64,
+    pub rng: Rng,
+    pub improved_at: u64,
+    // The best racer so far, and its fitness.
+    pub best: Option<(Brain, Fitness)>,
+    pub brains: Vec<Brain>,
+}
+
+impl Checkpoint {
+    // The file is replaced atomically so an interrupted save leaves the old one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        self.write_to(&mut file)?;
+        file.into_inner()?.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, LoadError> {
+        Checkpoint::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        write_u32(w, self.args.len() as u32)?;
+        for arg in self.args.iter() {
+            write_str(w, arg)?;
+        }
+        write_u64(w, self.generation)?;
+        write_u64(w, self.rng.state())?;
+        let fitness = match &self.best {
+            Some((_, fitness)) => *fitness,
+            None => Fitness {
+                score: 0.0,
+                time: 0.0,
+                finished: false,
+            },
+        };
+        write_f64(w, fitness.score)?;
+        write_f64(w, fitness.time)?;
+        write_u8(w, fitness.finished as u8)?;
+        write_u64(w, self.improved_at)?;
+        match &self.best {
+            Some((best, _)) => {
+                write_u8(w, 1)?;
+                best.write_to(w)?;
+            }
+            None => write_u8(w, 0)?,
+        }
+        write_u32(w, self.brains.len() as u32)?;
+        for brain in self.brains.iter() {
+            brain.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Checkpoint, LoadError> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = read_u32(r)?;
+        if version == 0 || version > VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let n_args = read_u32(r)?;
+        let mut args = Vec::new();
+        for _ in 0..n_args {
+            args.push(read_str(r)?.ok_or(LoadError::BadArgument)?);
+        }
+        let generation = read_u64(r)?;
+        let rng = Rng::with_seed(read_u64(r)?);
+        let fitness = match version {
+            1 => old_fitness(read_i32(r)? as f64),
+            2 | 3 => old_fitness(read_f64(r)?),
+            _ => Fitness {
+                score: read_f64(r)?,
+                time: read_f64(r)?,
+                finished: match read_u8(r)? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(LoadError::BadFinishedFlag),
+                },
+            },
+        };
+        let improved_at = if version < 3 {
+            generation
+        } else {
+            read_u64(r)?
+        };
+        let best = match read_u8(r)? {
+            0 => None,
+            1 => Some((Brain::decode(r)?, fitness)),
+            _ => return Err(LoadError::BadFlag),
+        };
+        let n_brains = read_u32(r)?;
+        let mut brains = Vec::new();
+        for _ in 0..n_brains {
+            brains.push(Brain::decode(r)?);
+        }
+        if !at_end(r)? {
+            return Err(LoadError::TrailingData);
+        }
+        Ok(Checkpoint {
+            args,
+            generation,
+            rng,
+            improved_at,
+            best,
+            brains,
+        })
+    }
+}
+
+// The fitness of a best racer saved with only its score, which nothing with the
+// same score beats.
+fn old_fitness(score: f64) -> Fitness {
+    Fitness {
+        score,
+        time: 0.0,
+        finished: true,
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u32),
+    BadArgument,
+    BadFlag,
+    BadFinishedFlag,
+    Brain(brain::LoadError),
+    TrailingData,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Truncated => write!(f, "checkpoint file is truncated"),
+            LoadError::BadMagic => write!(f, "not a checkpoint file"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported checkpoint file version {}", version)
+            }
+            LoadError::BadArgument => write!(f, "checkpoint has an argument that is not UTF-8"),
+            LoadError::BadFlag => write!(f, "checkpoint has a corrupt best brain flag"),
+            LoadError::BadFinishedFlag => {
+                write!(f, "checkpoint has a corrupt best racer finished flag")
+            }
+            LoadError::Brain(err) => write!(f, "checkpoint has a bad brain: {}", err),
+            LoadError::TrailingData => write!(f, "checkpoint file has trailing data"),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Brain(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            LoadError::Truncated
+        } else {
+            LoadError::Io(err)
+        }
+    }
+}
+
+impl From<brain::LoadError> for LoadError {
+    fn from(err: brain::LoadError) -> LoadError {
+        match err {
+            brain::LoadError::Truncated => LoadError::Truncated,
+            err => LoadError::Brain(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brain::{Activation, HiddenLayer};
+
+    fn checkpoint() -> Checkpoint {
+        let mut rng = Rng::with_seed(3);
+        let brains = std::iter::repeat_with(|| {
+            Brain::builder()
+                .view_dist(4)
+                .hidden_layers(vec![HiddenLayer::new(6, Activation::Relu)])
+                .random(&mut rng)
+        })
+        .take(4)
+        .collect::<Vec<_>>();
+        Checkpoint {
+            args: vec!["--seed".to_string(), "3".to_string()],
+            generation: 12,
+            best: Some((
+                brains[1].clone(),
+                Fitness {
+                    score: 40.5,
+                    time: 33.0,
+                    finished: true,
+                },
+            )),
+            rng,
+            improved_at: 9,
+            brains,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let checkpoint = checkpoint();
+        let mut buf = Vec::new();
+        checkpoint.write_to(&mut buf).unwrap();
+        let loaded = Checkpoint::read_from(&mut &buf[..]).unwrap();
+        assert!(loaded == checkpoint);
+    }
+
+    #[test]
+    fn reads_version_2() {
+        let checkpoint = checkpoint();
+        let mut buf = Vec::new();
+        checkpoint.write_to(&mut buf).unwrap();
+        // Keep only the best score, and drop the best time, the finished flag,
+        // and improved_at after it.
+        let args_len = checkpoint
+            .args
+            .iter()
+            .map(|arg| 4 + arg.len())
+            .sum::<usize>();
+        let at = 8 + 4 + 4 + args_len + 8 + 8 + 8;
+        buf.drain(at..at + 8 + 1 + 8);
+        buf[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let loaded = Checkpoint::read_from(&mut &buf[..]).unwrap();
+        assert_eq!(loaded.improved_at, checkpoint.generation);
+        let (brain, _) = checkpoint.best.clone().unwrap();
+        let fitness = loaded.best.as_ref().unwrap().1;
+        assert!(
+            loaded
+                == Checkpoint {
+                    improved_at: checkpoint.generation,
+                    best: Some((brain, old_fitness(40.5))),
+                    ..checkpoint
+                }
+        );
+        // Only a higher score beats it.
+        let finisher = Fitness {
+            score: 40.5,
+            time: 1.0,
+            finished: true,
+        };
+        assert_eq!(finisher.rank(&fitness), std::cmp::Ordering::Greater);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut buf = Vec::new();
+        checkpoint().write_to(&mut buf).unwrap();
+        for len in [0, 10, 30, buf.len() / 2, buf.len() - 1].iter() {
+            assert!(matches!(
+                Checkpoint::read_from(&mut &buf[..*len]),
+                Err(LoadError::Truncated)
+            ));
+        }
+    }
+}
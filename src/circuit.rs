@@ -0,0 +1,191 @@
+use crate::generator::WidthProfile;
+use crate::obstacles::{Obstacles, OBSTACLE_SLOT};
+use crate::rng::Rng;
+use crate::vector::{self, Vector};
+
+use std::f64::consts::PI;
+
+// A closed loop of track at absolute positions. Racers start at the first
+// checkpoint, which is also the finish line, and must pass the checkpoints in
+// order to go around.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Circuit {
+    min: Vector,
+    width: i32,
+    height: i32,
+    cells: Vec<bool>,
+    checkpoints: Vec<Vector>,
+    checkpoint_radius: i32,
+    laps: u32,
+}
+
+impl Circuit {
+    // The loop goes counterclockwise through a few corners at random distances
+    // around a center, so it turns every way.
+    pub fn generate(width: WidthProfile, obstacles: Obstacles, laps: u32, seed: u64) -> Circuit {
+        let path_radius = width.max_radius();
+        let mut rng = Rng::with_seed(seed);
+        let n_corners = 6 + rng.below(5) as usize;
+        let size = 24.0 + 24.0 * rng.fraction() + 4.0 * path_radius as f64;
+        let corners = (0..n_corners)
+            .map(|i| {
+                let angle = (i as f64 + (rng.fraction() - 0.5) * 0.5) / n_corners as f64 * 2.0 * PI;
+                let dist = size * (0.6 + 0.4 * rng.fraction());
+                Vector::new(
+                    (angle.cos() * dist).round() as i32,
+                    (angle.sin() * dist).round() as i32,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut path: Vec<Vector> = Vec::new();
+        for (i, &corner) in corners.iter().enumerate() {
+            let next = corners[(i + 1) % n_corners];
+            for pt in corner.supercover_pts(next) {
+                if path.last() != Some(&pt) {
+                    path.push(pt);
+                }
+            }
+        }
+        // The last corner leads back to the first.
+        path.pop();
+        let min = Vector::new(
+            path.iter().map(|pt| pt.x).min().unwrap() - path_radius,
+            path.iter().map(|pt| pt.y).min().unwrap() - path_radius,
+        );
+        let max = Vector::new(
+            path.iter().map(|pt| pt.x).max().unwrap() + path_radius,
+            path.iter().map(|pt| pt.y).max().unwrap() + path_radius,
+        );
+        let checkpoint_radius = path_radius + 1;
+        let checkpoints = place_checkpoints(&path, checkpoint_radius);
+        let mut circuit = Circuit {
+            min,
+            width: max.x - min.x + 1,
+            height: max.y - min.y + 1,
+            cells: vec![false; ((max.x - min.x + 1) * (max.y - min.y + 1)) as usize],
+            checkpoints,
+            checkpoint_radius,
+            laps: u32::max(1, laps),
+        };
+        let discs = (width.min_radius()..=path_radius)
+            .map(|radius| vector::circle_pts(radius).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for (i, &center) in path.iter().enumerate() {
+            let disc = &discs[(width.radius(i as i32) - width.min_radius()) as usize];
+            for &pt in disc.iter() {
+                if let Some(i) = circuit.index(center + pt) {
+                    circuit.cells[i] = true;
+                }
+            }
+        }
+        let len = path.len() as i32;
+        let center = |n: i32| path[n.rem_euclid(len) as usize];
+        let radius = |n: i32| width.radius(n.rem_euclid(len));
+        for slot in 0..len / OBSTACLE_SLOT {
+            for cell in obstacles.blocked(slot, center, radius) {
+                // The last slot can reach the start.
+                if cell == path[0] {
+                    continue;
+                }
+                if let Some(i) = circuit.index(cell) {
+                    circuit.cells[i] = false;
+                }
+            }
+        }
+        circuit
+    }
+
+    pub fn is_open(&self, pos: Vector) -> bool {
+        match self.index(pos) {
+            Some(i) => self.cells[i],
+            None => false,
+        }
+    }
+
+    fn index(&self, pos: Vector) -> Option<usize> {
+        let x = pos.x - self.min.x;
+        let y = pos.y - self.min.y;
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            Some((x + y * self.width) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn start(&self) -> Vector {
+        self.checkpoints[0]
+    }
+
+    pub fn checkpoints(&self) -> &[Vector] {
+        &self.checkpoints
+    }
+
+    // A checkpoint is passed by coming within this distance of it, which
+    // reaches across the track.
+    pub fn checkpoint_radius(&self) -> i32 {
+        self.checkpoint_radius
+    }
+
+    pub fn at_checkpoint(&self, checkpoint: usize, pos: Vector) -> bool {
+        let d = pos - self.checkpoints[checkpoint];
+        let r = self.checkpoint_radius;
+        d.x * d.x + d.y * d.y <= r * r
+    }
+
+    // How many times around finishes a race.
+    pub fn laps(&self) -> u32 {
+        self.laps
+    }
+}
+
+// Places each checkpoint at the first cell of the path more than twice the
+// radius from the one before, so no position is at two checkpoints at once and
+// a racer at the start is not yet at the next one.
+fn place_checkpoints(path: &[Vector], radius: i32) -> Vec<Vector> {
+    let apart = |a: Vector, b: Vector| {
+        let d = a - b;
+        d.x * d.x + d.y * d.y > 4 * radius * radius
+    };
+    let mut checkpoints = vec![path[0]];
+    for &pt in path.iter() {
+        if apart(pt, *checkpoints.last().unwrap()) {
+            checkpoints.push(pt);
+        }
+    }
+    // The last one must be as far from the first, which follows it.
+    if !apart(*checkpoints.last().unwrap(), path[0]) {
+        checkpoints.pop();
+    }
+    checkpoints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_is_open_and_closed() {
+        for seed in 0..20 {
+            let circuit =
+                Circuit::generate(WidthProfile::new(1, 3, 2, seed), Obstacles::none(), 2, seed);
+            let checkpoints = circuit.checkpoints();
+            assert!(checkpoints.len() > 10);
+            let r = circuit.checkpoint_radius() as f64;
+            for (i, &checkpoint) in checkpoints.iter().enumerate() {
+                assert!(circuit.is_open(checkpoint));
+                // Consecutive checkpoints, including the last and the first,
+                // are far enough apart that no position is at both, and close
+                // enough that none can be skipped.
+                let next = checkpoints[(i + 1) % checkpoints.len()];
+                let d = next - checkpoint;
+                let dist = f64::hypot(d.x as f64, d.y as f64);
+                assert!(dist > 2.0 * r, "seed {}", seed);
+                assert!(dist <= 4.0 * r + 2.0, "seed {}", seed);
+            }
+            // The center of the loop is walled off.
+            assert!(!circuit.is_open(Vector::ORIGIN));
+            assert!(!circuit.is_open(Vector::new(1000, 0)));
+            assert_eq!(circuit.laps(), 2);
+        }
+    }
+}
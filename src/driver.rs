@@ -0,0 +1,167 @@
+use crate::brain::Brain;
+use crate::racetrack::Racetrack;
+use crate::vector::{Traversal, Vector};
+
+use std::io::BufRead;
+
+// Something that decides how a racer accelerates each tick. The track is
+// centered on the racer.
+pub trait Driver {
+    fn accel(&mut self, vel: Vector, track: &Racetrack) -> Vector;
+}
+
+impl Driver for Brain {
+    fn accel(&mut self, vel: Vector, track: &Racetrack) -> Vector {
+        self.compute_accel(vel, track)
+    }
+}
+
+const ACCELS: [Vector; 5] = [
+    Vector { x: 1, y: 0 },
+    Vector { x: 0, y: 1 },
+    Vector { x: -1, y: 0 },
+    Vector { x: 0, y: -1 },
+    Vector { x: 0, y: 0 },
+];
+
+// Searches a few ticks ahead for the moves that get furthest north without
+// hitting a visible wall.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GreedyDriver {
+    lookahead: u32,
+    traversal: Traversal,
+}
+
+impl GreedyDriver {
+    pub fn new(lookahead: u32) -> GreedyDriver {
+        GreedyDriver::with_traversal(lookahead, Traversal::default())
+    }
+
+    // The traversal should be the one the race uses to find collisions.
+    pub fn with_traversal(lookahead: u32, traversal: Traversal) -> GreedyDriver {
+        GreedyDriver {
+            lookahead: u32::max(1, lookahead),
+            traversal,
+        }
+    }
+
+    // Returns the furthest north the racer can safely be after the given
+    // number of ticks, or None if it must crash.
+    fn best_y(&self, pos: Vector, vel: Vector, ticks: u32, track: &Racetrack) -> Option<i32> {
+        if ticks == 0 {
+            return Some(pos.y);
+        }
+        ACCELS
+            .iter()
+            .filter_map(|&accel| {
+                let vel = vel + accel;
+                let blocked = self
+                    .traversal
+                    .pts(pos, pos + vel)
+                    .any(|pt| track.get(pt) == Some(false));
+                if blocked {
+                    None
+                } else {
+                    self.best_y(pos + vel, vel, ticks - 1, track)
+                }
+            })
+            .max()
+    }
+}
+
+impl Default for GreedyDriver {
+    fn default() -> GreedyDriver {
+        GreedyDriver::new(3)
+    }
+}
+
+impl Driver for GreedyDriver {
+    fn accel(&mut self, vel: Vector, track: &Racetrack) -> Vector {
+        let mut best = None;
+        for &accel in ACCELS.iter() {
+            let vel = vel + accel;
+            let blocked = self
+                .traversal
+                .pts(Vector::ORIGIN, vel)
+                .any(|pt| track.get(pt) == Some(false));
+            if blocked {
+                continue;
+            }
+            if let Some(y) = self.best_y(vel, vel, self.lookahead - 1, track) {
+                match best {
+                    Some((best_y, _)) if best_y >= y => (),
+                    _ => best = Some((y, accel)),
+                }
+            }
+        }
+        best.map_or(Vector::ORIGIN, |(_, accel)| accel)
+    }
+}
+
+// Reads one line per tick. A line starting with w, a, s, or d accelerates
+// north, west, south, or east. Anything else, including the end of the input,
+// coasts.
+pub struct HumanDriver<R> {
+    input: R,
+}
+
+impl<R: BufRead> HumanDriver<R> {
+    pub fn new(input: R) -> HumanDriver<R> {
+        HumanDriver { input }
+    }
+}
+
+impl<R: BufRead> Driver for HumanDriver<R> {
+    fn accel(&mut self, _vel: Vector, _track: &Racetrack) -> Vector {
+        let mut line = String::new();
+        if self.input.read_line(&mut line).is_err() {
+            return Vector::ORIGIN;
+        }
+        match line.trim_start().chars().next() {
+            Some('w') => Vector::new(0, 1),
+            Some('a') => Vector::new(-1, 0),
+            Some('s') => Vector::new(0, -1),
+            Some('d') => Vector::new(1, 0),
+            _ => Vector::ORIGIN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_keys() {
+        let mut driver = HumanDriver::new(&b"w\n a\ns\nd\n\nx\n"[..]);
+        let mut track = Racetrack::builder().view_dist(4).seed(1).build();
+        track.translate(Vector::ORIGIN);
+        let accels = std::iter::repeat_with(|| driver.accel(Vector::ORIGIN, &track))
+            .take(7)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            accels,
+            vec![
+                Vector::new(0, 1),
+                Vector::new(-1, 0),
+                Vector::new(0, -1),
+                Vector::new(1, 0),
+                Vector::ORIGIN,
+                Vector::ORIGIN,
+                Vector::ORIGIN,
+            ]
+        );
+    }
+
+    #[test]
+    fn greedy_avoids_visible_walls() {
+        let mut track = Racetrack::builder().view_dist(10).seed(6).build();
+        track.translate(Vector::ORIGIN);
+        let mut driver = GreedyDriver::default();
+        let vel = Vector::new(0, 1);
+        let next = vel + driver.accel(vel, &track);
+        assert!(Vector::ORIGIN
+            .segment_pts(next)
+            .all(|pt| track.get(pt) != Some(false)));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::race::{EndCause, RaceOutcome};
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+// How well a racer did over one or more races, with the time in ticks. A
+// higher score is better. Then a racer that finished every race is better than
+// one that did not, and did better the sooner it finished. A racer that did not
+// finish did better the longer it survived.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub struct Fitness {
+    pub score: f64,
+    pub time: f64,
+    pub finished: bool,
+}
+
+impl Fitness {
+    pub fn from_outcome(outcome: &RaceOutcome) -> Fitness {
+        Fitness {
+            score: outcome.score as f64,
+            time: outcome.time as f64,
+            finished: outcome.end == EndCause::Finish,
+        }
+    }
+
+    // Orders better fitness first.
+    pub fn rank(&self, other: &Fitness) -> Ordering {
+        let by_time = if self.finished && other.finished {
+            self.time.partial_cmp(&other.time)
+        } else {
+            other.time.partial_cmp(&self.time)
+        };
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap_or(Ordering::Equal)
+            .then(other.finished.cmp(&self.finished))
+            .then(by_time.unwrap_or(Ordering::Equal))
+    }
+}
+
+// Ways to combine the results of several races into one fitness. Scores and
+// times are combined separately. Min takes the worst of each: the lowest score,
+// and the longest time if every race finished or else the shortest. The result
+// is finished only if every race was. A racer that finished only some races
+// ranks by how long it survived the others, so only their times are combined.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Aggregate {
+    Mean,
+    Min,
+    Median,
+}
+
+impl Aggregate {
+    pub fn apply(self, outcomes: &[RaceOutcome]) -> Fitness {
+        assert!(!outcomes.is_empty(), "aggregate of no races");
+        let fitnesses = outcomes
+            .iter()
+            .map(Fitness::from_outcome)
+            .collect::<Vec<_>>();
+        let mut scores = fitnesses
+            .iter()
+            .map(|fitness| fitness.score)
+            .collect::<Vec<_>>();
+        let finished = fitnesses.iter().all(|fitness| fitness.finished);
+        let mut times = fitnesses
+            .iter()
+            .filter(|fitness| fitness.finished == finished)
+            .map(|fitness| fitness.time)
+            .collect::<Vec<_>>();
+        match self {
+            Aggregate::Mean => Fitness {
+                score: mean(&scores),
+                time: mean(&times),
+                finished,
+            },
+            Aggregate::Min => Fitness {
+                score: scores.iter().cloned().fold(f64::INFINITY, f64::min),
+                time: if finished {
+                    times.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+                } else {
+                    times.iter().cloned().fold(f64::INFINITY, f64::min)
+                },
+                finished,
+            },
+            Aggregate::Median => Fitness {
+                score: median(&mut scores),
+                time: median(&mut times),
+                finished,
+            },
+        }
+    }
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Sorts the values.
+pub fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len() & 1 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+// The spread of a set of values, such as the scores of a generation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Summary {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub stddev: f64,
+}
+
+impl Summary {
+    pub fn of(values: &[f64]) -> Summary {
+        assert!(!values.is_empty(), "summary of nothing");
+        let mean = mean(values);
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+        Summary {
+            min: values.iter().cloned().fold(f64::INFINITY, f64::min),
+            mean,
+            max: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            stddev: variance.sqrt(),
+        }
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aggregate, String> {
+        match s {
+            "mean" => Ok(Aggregate::Mean),
+            "min" => Ok(Aggregate::Min),
+            "median" => Ok(Aggregate::Median),
+            _ => Err(format!("unknown aggregate: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Aggregate::Mean => "mean",
+            Aggregate::Min => "min",
+            Aggregate::Median => "median",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(score: i32, time: usize) -> RaceOutcome {
+        RaceOutcome {
+            score,
+            time,
+            end: EndCause::Stagnation,
+            crash_point: None,
+        }
+    }
+
+    fn finish(score: i32, time: usize) -> RaceOutcome {
+        RaceOutcome {
+            end: EndCause::Finish,
+            ..outcome(score, time)
+        }
+    }
+
+    #[test]
+    fn aggregates() {
+        let outcomes = [outcome(10, 5), outcome(2, 9), outcome(6, 1), outcome(0, 3)];
+        assert_eq!(
+            Aggregate::Mean.apply(&outcomes),
+            Fitness {
+                score: 4.5,
+                time: 4.5,
+                finished: false
+            }
+        );
+        assert_eq!(
+            Aggregate::Min.apply(&outcomes),
+            Fitness {
+                score: 0.0,
+                time: 1.0,
+                finished: false
+            }
+        );
+        assert_eq!(
+            Aggregate::Median.apply(&outcomes[..3]),
+            Fitness {
+                score: 6.0,
+                time: 5.0,
+                finished: false
+            }
+        );
+    }
+
+    #[test]
+    fn min_takes_the_slowest_finish() {
+        let outcomes = [finish(30, 40), finish(30, 25), finish(30, 60)];
+        assert_eq!(
+            Aggregate::Min.apply(&outcomes),
+            Fitness {
+                score: 30.0,
+                time: 60.0,
+                finished: true
+            }
+        );
+        let steady = Aggregate::Min.apply(&[finish(30, 45), finish(30, 45)]);
+        let erratic = Aggregate::Min.apply(&[finish(30, 20), finish(30, 50)]);
+        assert_eq!(steady.rank(&erratic), Ordering::Less);
+    }
+
+    #[test]
+    fn partly_finished_races_combine_survival_times() {
+        let outcomes = [finish(30, 40), outcome(12, 90), outcome(20, 70)];
+        for &aggregate in [Aggregate::Mean, Aggregate::Min, Aggregate::Median].iter() {
+            let fitness = aggregate.apply(&outcomes);
+            assert!(!fitness.finished);
+            let expected = match aggregate {
+                Aggregate::Min => 70.0,
+                _ => 80.0,
+            };
+            assert_eq!(fitness.time, expected, "{}", aggregate);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        assert_eq!(
+            Summary::of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]),
+            Summary {
+                min: 2.0,
+                mean: 5.0,
+                max: 9.0,
+                stddev: 2.0
+            }
+        );
+    }
+
+    #[test]
+    fn sooner_finish_is_better() {
+        let finish = |time| {
+            Fitness::from_outcome(&RaceOutcome {
+                score: 30,
+                time,
+                end: EndCause::Finish,
+                crash_point: None,
+            })
+        };
+        assert_eq!(finish(40).rank(&finish(60)), Ordering::Less);
+        let outcome = |time| RaceOutcome {
+            score: 30,
+            time,
+            end: EndCause::Finish,
+            crash_point: None,
+        };
+        let sooner = Aggregate::Mean.apply(&[outcome(30), outcome(50)]);
+        let later = Aggregate::Mean.apply(&[outcome(40), outcome(60)]);
+        assert_eq!(sooner.time, 40.0);
+        assert!(sooner.finished);
+        assert_eq!(sooner.rank(&later), Ordering::Less);
+        // Finishing beats surviving longer with the same score.
+        let survived = Fitness::from_outcome(&RaceOutcome {
+            score: 30,
+            time: 500,
+            end: EndCause::Crash,
+            crash_point: None,
+        });
+        assert_eq!(finish(60).rank(&survived), Ordering::Less);
+        // Only finishing every race counts as finished.
+        let mut outcomes = [outcome(30), outcome(30)];
+        outcomes[1].end = EndCause::Stagnation;
+        assert!(!Aggregate::Mean.apply(&outcomes).finished);
+    }
+
+    #[test]
+    fn rank_prefers_score_then_survival() {
+        let mut fitnesses = [
+            Fitness {
+                score: 1.0,
+                time: 1.0,
+                finished: false,
+            },
+            Fitness {
+                score: 3.0,
+                time: 8.0,
+                finished: false,
+            },
+            Fitness {
+                score: 3.0,
+                time: 2.0,
+                finished: false,
+            },
+        ];
+        fitnesses.sort_by(Fitness::rank);
+        let order = fitnesses
+            .iter()
+            .map(|f| (f.score, f.time))
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![(3.0, 8.0), (3.0, 2.0), (1.0, 1.0)]);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::rng::Rng;
+use crate::vector::Vector;
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// Makes the center line of a corridor one row at a time. A generator must be
+// able to go back the way it came, so that moving south and then north again
+// gives the same path.
+pub trait TrackGenerator: Send + fmt::Debug {
+    // The absolute position of the path at the current row. This starts at the
+    // origin.
+    fn pos(&self) -> Vector;
+
+    fn move_north(&mut self);
+
+    fn move_south(&mut self);
+}
+
+// The families of generators.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum TrackStyle {
+    // The path's sideways velocity drifts by -1, 0, or 1 each row.
+    Walk,
+    // The path sways along the sum of two sine waves.
+    Sine,
+    // The path goes straight diagonally and turns sharply back at regular rows.
+    Zigzag,
+    // The path's heading turns a little each row, so it curves smoothly but
+    // never goes sideways too fast.
+    Curve,
+}
+
+impl TrackStyle {
+    pub const ALL: [TrackStyle; 4] = [
+        TrackStyle::Walk,
+        TrackStyle::Sine,
+        TrackStyle::Zigzag,
+        TrackStyle::Curve,
+    ];
+
+    pub fn generator(self, seed: u64) -> Box<dyn TrackGenerator> {
+        match self {
+            TrackStyle::Walk => Box::new(RandomWalk::new(seed)),
+            TrackStyle::Sine => Box::new(Sine::new(seed)),
+            TrackStyle::Zigzag => Box::new(Zigzag::new(seed)),
+            TrackStyle::Curve => Box::new(Curve::new(seed)),
+        }
+    }
+}
+
+impl FromStr for TrackStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TrackStyle, String> {
+        match s {
+            "walk" => Ok(TrackStyle::Walk),
+            "sine" => Ok(TrackStyle::Sine),
+            "zigzag" => Ok(TrackStyle::Zigzag),
+            "curve" => Ok(TrackStyle::Curve),
+            _ => Err(format!("unknown track style: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TrackStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TrackStyle::Walk => "walk",
+            TrackStyle::Sine => "sine",
+            TrackStyle::Zigzag => "zigzag",
+            TrackStyle::Curve => "curve",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RandomWalk {
+    pos: Vector,
+    vel_x: i32,
+    rng: Rng,
+}
+
+impl RandomWalk {
+    pub fn new(seed: u64) -> RandomWalk {
+        RandomWalk {
+            pos: Vector::ORIGIN,
+            vel_x: 0,
+            rng: Rng::with_seed(seed),
+        }
+    }
+}
+
+impl TrackGenerator for RandomWalk {
+    fn pos(&self) -> Vector {
+        self.pos
+    }
+
+    fn move_north(&mut self) {
+        self.pos.x += self.vel_x;
+        self.vel_x += (self.rng.forward() / (Rng::RAND_MAX / 3)) as i32 - 1;
+        self.pos.y += 1;
+    }
+
+    fn move_south(&mut self) {
+        self.vel_x -= (self.rng.backward() / (Rng::RAND_MAX / 3)) as i32 - 1;
+        self.pos.x -= self.vel_x;
+        self.pos.y -= 1;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sine {
+    y: i32,
+    // The amplitude, angular frequency, and phase of each wave.
+    waves: [(f64, f64, f64); 2],
+}
+
+impl Sine {
+    pub fn new(seed: u64) -> Sine {
+        let mut rng = Rng::with_seed(seed);
+        rng.forward();
+        let mut wave = |min_period: f64, max_period: f64, amplitude: f64| {
+            let period = min_period + (max_period - min_period) * rng.fraction();
+            let phase = rng.fraction() * 2.0 * PI;
+            (amplitude * (0.5 + rng.fraction()), 2.0 * PI / period, phase)
+        };
+        let waves = [wave(60.0, 120.0, 12.0), wave(15.0, 40.0, 3.0)];
+        Sine { y: 0, waves }
+    }
+
+    fn x(&self, y: i32) -> f64 {
+        self.waves
+            .iter()
+            .map(|&(amplitude, frequency, phase)| amplitude * (y as f64 * frequency + phase).sin())
+            .sum()
+    }
+}
+
+impl TrackGenerator for Sine {
+    // The path is shifted sideways to start at the origin.
+    fn pos(&self) -> Vector {
+        Vector::new((self.x(self.y) - self.x(0)).round() as i32, self.y)
+    }
+
+    fn move_north(&mut self) {
+        self.y += 1;
+    }
+
+    fn move_south(&mut self) {
+        self.y -= 1;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Zigzag {
+    pos: Vector,
+    seed: u64,
+    // How many rows each straight leg is.
+    leg: i32,
+}
+
+impl Zigzag {
+    pub fn new(seed: u64) -> Zigzag {
+        Zigzag {
+            pos: Vector::ORIGIN,
+            seed,
+            leg: 10 + hash(seed, 0).rem_euclid(15) as i32,
+        }
+    }
+
+    // The sideways step of the leg with the row, which alternates direction.
+    fn slope(&self, y: i32) -> i32 {
+        let leg = y.div_euclid(self.leg);
+        let slope = 1 + hash(self.seed, leg as i64 + 1).rem_euclid(3) as i32;
+        if leg & 1 == 1 {
+            -slope
+        } else {
+            slope
+        }
+    }
+}
+
+impl TrackGenerator for Zigzag {
+    fn pos(&self) -> Vector {
+        self.pos
+    }
+
+    fn move_north(&mut self) {
+        self.pos.x += self.slope(self.pos.y);
+        self.pos.y += 1;
+    }
+
+    fn move_south(&mut self) {
+        self.pos.y -= 1;
+        self.pos.x -= self.slope(self.pos.y);
+    }
+}
+
+// The heading turns in steps of this many radians.
+const CURVE_TURN: f64 = PI / 32.0;
+// The sideways step when heading due east or west.
+const CURVE_SPEED: f64 = 3.0;
+
+#[derive(Clone, Debug)]
+pub struct Curve {
+    pos: Vector,
+    // In steps of CURVE_TURN counterclockwise from north. The sideways step
+    // goes with the sine of the heading, so it is never too fast however far
+    // the heading turns, and turning it back is exact.
+    heading: i32,
+    seed: u64,
+}
+
+impl Curve {
+    pub fn new(seed: u64) -> Curve {
+        Curve {
+            pos: Vector::ORIGIN,
+            heading: 0,
+            seed,
+        }
+    }
+
+    // How the heading turns going north from the row.
+    fn turn(&self, y: i32) -> i32 {
+        hash(self.seed, y as i64).rem_euclid(3) as i32 - 1
+    }
+
+    fn step(&self) -> i32 {
+        -((self.heading as f64 * CURVE_TURN).sin() * CURVE_SPEED).round() as i32
+    }
+}
+
+impl TrackGenerator for Curve {
+    fn pos(&self) -> Vector {
+        self.pos
+    }
+
+    fn move_north(&mut self) {
+        self.pos.x += self.step();
+        self.heading += self.turn(self.pos.y);
+        self.pos.y += 1;
+    }
+
+    fn move_south(&mut self) {
+        self.pos.y -= 1;
+        self.heading -= self.turn(self.pos.y);
+        self.pos.x -= self.step();
+    }
+}
+
+// How many rows a path keeps its radius at the end of each section, after
+// changing to it.
+const WIDTH_PLATEAU: i32 = 24;
+
+// How the radius of a path changes along it. Each section of the path heads
+// toward a new radius, sometimes the narrowest for a chicane, changing by one
+// every so many rows on the way.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct WidthProfile {
+    min_radius: i32,
+    max_radius: i32,
+    rate: i32,
+    seed: u64,
+}
+
+impl WidthProfile {
+    pub fn constant(radius: i32) -> WidthProfile {
+        WidthProfile::new(radius, radius, 1, 0)
+    }
+
+    // The rate is how many rows the radius takes to change by one.
+    pub fn new(min_radius: i32, max_radius: i32, rate: i32, seed: u64) -> WidthProfile {
+        let (a, b) = (i32::abs(min_radius), i32::abs(max_radius));
+        WidthProfile {
+            min_radius: i32::min(a, b),
+            max_radius: i32::max(a, b),
+            rate: i32::max(1, rate),
+            seed: derive_seed(seed, WIDTH_SALT),
+        }
+    }
+
+    pub fn min_radius(&self) -> i32 {
+        self.min_radius
+    }
+
+    pub fn max_radius(&self) -> i32 {
+        self.max_radius
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.min_radius == self.max_radius
+    }
+
+    // The radius at the nth row or cell along the path.
+    pub fn radius(&self, n: i32) -> i32 {
+        if self.is_constant() {
+            return self.max_radius;
+        }
+        // Sections are long enough to change all the way between any radii.
+        let len = (self.max_radius - self.min_radius) * self.rate + WIDTH_PLATEAU;
+        let section = n.div_euclid(len);
+        let from = self.target(section - 1);
+        let to = self.target(section);
+        let change = i32::min(i32::abs(to - from), (n - section * len) / self.rate);
+        from + (to - from).signum() * change
+    }
+
+    fn target(&self, section: i32) -> i32 {
+        let h = hash(self.seed, section as i64);
+        if h.rem_euclid(4) == 0 {
+            self.min_radius
+        } else {
+            self.min_radius
+                + (h / 4).rem_euclid((self.max_radius - self.min_radius + 1) as i64) as i32
+        }
+    }
+}
+
+// The salts of the seeds derived from the seed of a track, one for each
+// feature of the track that has its own.
+pub(crate) const WIDTH_SALT: i64 = 0;
+pub(crate) const OBSTACLES_SALT: i64 = 1;
+
+// The seed of a feature of a track, such as its width, from the seed of the
+// track. It is unrelated to the seed of the path and to those of the other
+// features, so the feature does not follow the turns of the path.
+pub(crate) fn derive_seed(seed: u64, salt: i64) -> u64 {
+    hash(!seed, salt) as u64
+}
+
+// Mixes the seed and a number into a well spread number (splitmix64).
+pub(crate) fn hash(seed: u64, n: i64) -> i64 {
+    let mut z = seed.wrapping_add((n as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_reverse() {
+        for &style in TrackStyle::ALL.iter() {
+            for seed in 0..20 {
+                let mut generator = style.generator(seed);
+                assert_eq!(generator.pos(), Vector::ORIGIN);
+                let mut north = Vec::new();
+                for _ in 0..300 {
+                    north.push(generator.pos());
+                    generator.move_north();
+                }
+                for _ in 0..500 {
+                    generator.move_south();
+                }
+                let mut south = Vec::new();
+                for _ in 0..500 {
+                    south.push(generator.pos());
+                    generator.move_north();
+                }
+                assert_eq!(south[200..], north[..], "{} {}", style, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn curves_are_bounded() {
+        let mut generator = Curve::new(7);
+        let mut last_step = 0;
+        for _ in 0..2000 {
+            let from = generator.pos();
+            generator.move_north();
+            let step = generator.pos().x - from.x;
+            assert!(i32::abs(step) <= CURVE_SPEED as i32);
+            assert!(i32::abs(step - last_step) <= 1);
+            last_step = step;
+        }
+    }
+
+    #[test]
+    fn widths_change_gradually() {
+        let width = WidthProfile::new(1, 6, 3, 11);
+        let radii = (-500..500).map(|n| width.radius(n)).collect::<Vec<_>>();
+        assert_eq!(*radii.iter().min().unwrap(), 1);
+        assert_eq!(*radii.iter().max().unwrap(), 6);
+        for run in radii.windows(3) {
+            assert!(i32::abs(run[2] - run[0]) <= 1);
+        }
+        assert!((0..100).all(|n| WidthProfile::constant(-4).radius(n) == 4));
+    }
+
+    #[test]
+    fn names() {
+        for &style in TrackStyle::ALL.iter() {
+            assert_eq!(style.to_string().parse(), Ok(style));
+        }
+    }
+}
@@ -1,5 +1,17 @@
+pub mod asciicast;
 pub mod brain;
+mod bytes;
+pub mod checkpoint;
+pub mod circuit;
+pub mod driver;
+pub mod fitness;
+pub mod generator;
 pub mod grid;
+pub mod map;
+pub mod obstacles;
+pub mod race;
 pub mod racetrack;
 pub mod rng;
+pub mod selection;
 pub mod vector;
+pub mod world;
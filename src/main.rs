@@ -1,19 +1,35 @@
 extern crate getopts;
 extern crate rayon;
+extern crate signal_hook;
 
 use getopts::Options;
 use rayon::{prelude::*, ThreadPoolBuilder};
+use std::cmp;
 use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
 use std::iter;
 use std::process;
 use std::str::FromStr;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::{Duration, SystemTime};
-use vec_rac::brain::Brain;
-use vec_rac::racetrack::Racetrack;
+use std::time::{Duration, Instant, SystemTime};
+use vec_rac::asciicast::Recorder;
+use vec_rac::brain::{Actions, Activation, Brain, Crossover, HiddenLayer, Sensors};
+use vec_rac::checkpoint::Checkpoint;
+use vec_rac::driver::{Driver, GreedyDriver, HumanDriver};
+use vec_rac::fitness::{self, Aggregate, Fitness, Summary};
+use vec_rac::generator::TrackStyle;
+use vec_rac::map::TrackMap;
+use vec_rac::race::{EndCause, Race};
+use vec_rac::racetrack::{Racetrack, TrackShape};
 use vec_rac::rng::Rng;
-use vec_rac::vector::Vector;
+use vec_rac::selection::Selection;
+use vec_rac::vector::{Traversal, Vector};
+
+use signal_hook::consts::SIGINT;
 
 fn options() -> Options {
     let mut opts = Options::new();
@@ -37,16 +53,105 @@ fn options() -> Options {
         "Set track path radius. This is a positive integer. The default is 4.",
         "RADIUS",
     );
+    opts.optopt(
+        "",
+        "min-path-radius",
+        "Set the narrowest the track path gets. The path radius then changes along the track between this and path-radius, with narrow chicanes here and there. This is a positive integer. The default is path-radius, for a path of constant width.",
+        "RADIUS",
+    );
+    opts.optopt(
+        "",
+        "width-rate",
+        "Set how many rows the track path takes to change its radius by one. This is a positive integer. The default is 4.",
+        "ROWS",
+    );
+    opts.optopt(
+        "",
+        "obstacle-density",
+        "Set the chance of an obstacle in each stretch of the track: a pillar, a boulder, or an island splitting the path into two lanes. This is a number from 0 to 1. The default is 0, for no obstacles.",
+        "DENSITY",
+    );
+    opts.optopt(
+        "",
+        "track",
+        "Race on a track drawn in a text file instead of generated ones. '#' is a wall, ' ' and '.' are open, 'S' is the start, and 'F' is the finish line, which is optional. A first line like \"velocity 1 0\" sets the start velocity. The options for generating tracks are then ignored.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "track-shape",
+        "Set the shape of the tracks: corridor for a path going north forever, where racers score by how far north they get, or circuit for a closed loop, where racers score by the checkpoints they pass on the way around and finish after some laps. The default is corridor.",
+        "SHAPE",
+    );
+    opts.optopt(
+        "",
+        "track-style",
+        "Set how the path of a corridor winds: walk for a path whose sideways speed drifts at random, sine for smooth swaying, zigzag for straight diagonals with hairpin turns, or curve for smooth turns of bounded sharpness. The default is walk.",
+        "STYLE",
+    );
+    opts.optopt(
+        "",
+        "finish-dist",
+        "End corridors with a finish line this many rows north of the start. Racers that cross it rank above the rest by how few ticks they took, and the rest by how far north they got. This is a positive integer. The default is for corridors to go on forever.",
+        "ROWS",
+    );
+    opts.optopt(
+        "",
+        "laps",
+        "Set how many laps of a circuit finish a race. This is a positive integer. The default is 3.",
+        "COUNT",
+    );
     opts.optopt(
         "",
         "seed",
         "Set random seed to use. This is a positive integer. The default is decided randomly.",
         "SEED",
     );
+    opts.optopt(
+        "",
+        "train-seeds",
+        "Set how many tracks each racer is scored on. The tracks have consecutive seeds starting with the seed option. This is a positive integer. The default is 1.",
+        "COUNT",
+    );
+    opts.optflag(
+        "",
+        "resample-seeds",
+        "Score each generation on new tracks, with the seeds after those of the previous generation.",
+    );
+    opts.optopt(
+        "",
+        "aggregate",
+        "Set how the scores and times from several tracks are combined: mean, min, or median. The default is mean.",
+        "HOW",
+    );
+    opts.optopt(
+        "",
+        "validation-seeds",
+        "Set how many tracks the best racer is checked on without affecting selection. The tracks have consecutive seeds counting down from one below the seed option. This is a nonnegative integer. The default is 0.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "validate-every",
+        "Set how many generations pass between checks on the validation tracks. The training and validation scores are printed to standard error. This is a positive integer. The default is 10.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "champion-by",
+        "Set whether the best racer shown and saved is the one with the best training score or the best validation score. This is training or validation. The default is training.",
+        "SCORE",
+    );
+    opts.optopt(
+        "",
+        "collision",
+        "Set how the cells a move passes through are found to check for walls: stepped for steps of unit length, which can cut corners, supercover for every cell the move touches, or bresenham for a Bresenham line. The default is stepped.",
+        "TRAVERSAL",
+    );
     opts.optopt(
         "",
         "population",
-        "Set genome population size. This is a positive integer. It may be rounded a bit. The default is 10.",
+        "Set genome population size. This is a positive integer. The default is 10.",
         "SIZE",
     );
     opts.optopt(
@@ -55,17 +160,150 @@ fn options() -> Options {
         "Set mutation rate, a positive decimal. The default is 0.05.",
         "RATE",
     );
+    opts.optopt(
+        "",
+        "selection",
+        "Set how parents are picked from the population ranked by score: truncation to take turns with the better half, tournament:K for the best of K random racers, proportional for a chance weighted by score, or rank for a chance weighted by rank. The default is truncation.",
+        "METHOD",
+    );
+    opts.optopt(
+        "",
+        "elitism",
+        "Set how many of the best racers are kept unchanged in the next generation. This is a nonnegative integer no more than the population size. The default is half the population size.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "crossover-rate",
+        "Set the fraction of offspring made by crossing a survivor with another one and mutating the result instead of by only mutating the survivor. This is a decimal from 0 to 1. The default is 0.",
+        "FRACTION",
+    );
+    opts.optopt(
+        "",
+        "crossover",
+        "Set how crossover picks from the parents: uniform for each weight, neuron for each neuron, or point for a single point. The default is uniform.",
+        "KIND",
+    );
     opts.optopt(
         "",
         "testing-threads",
         "Set the number of threads to use to continuously test AIs. This is a positive integer. The default is probably the number of cores the computer has.",
         "COUNT",
     );
+    opts.optopt(
+        "",
+        "sensors",
+        "Set what new racers sense. This is grid to sense every second cell in view, or rays:N to sense the distance to walls along N rays around the racer. The default is grid.",
+        "SENSORS",
+    );
+    opts.optopt(
+        "",
+        "actions",
+        "Set how new racers can accelerate: cardinal to move one step east, north, west, or south or to coast, moore to change each velocity component by -1, 0, or 1, or moore:N to change each by up to N. The default is cardinal.",
+        "ACTIONS",
+    );
+    opts.optopt(
+        "",
+        "layers",
+        "Set the sizes of the hidden layers of new racers' neural networks, from the inputs towards the outputs. This is a comma-separated list of positive integers, which may be empty. The default is 32.",
+        "SIZES",
+    );
+    opts.optopt(
+        "",
+        "activation",
+        "Set the activation functions of the hidden layers of new racers' neural networks. This is either one of linear, tanh, relu, sigmoid, and step for all layers, or a comma-separated list with one for each layer. The default is tanh.",
+        "NAMES",
+    );
+    opts.optopt(
+        "",
+        "max-generations",
+        "Stop after COUNT generations.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "target-score",
+        "Stop once the best racer has a score of at least SCORE.",
+        "SCORE",
+    );
+    opts.optopt(
+        "",
+        "time-limit",
+        "Stop after the first generation to end SECONDS after the start.",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "stagnation-limit",
+        "Stop after COUNT generations in a row without a new best racer.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "save",
+        "Save each new best racer to FILE, overwriting it.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "load",
+        "Start the population from the racer saved in FILE and mutants of it. The view distance defaults to the racer's.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "drive",
+        "Instead of evolving racers, show one race driven by DRIVER and exit. DRIVER is greedy for a simple search, human to type w, a, s, or d and then enter each turn, or the file of a saved racer.",
+        "DRIVER",
+    );
+    opts.optflag(
+        "",
+        "headless",
+        "Show nothing but a line of progress for each generation. With drive, show only the result of the race.",
+    );
+    opts.optopt(
+        "",
+        "progress-format",
+        "Set the format of headless progress lines: text, or json for one JSON object per line. The default is text.",
+        "FORMAT",
+    );
+    opts.optopt(
+        "",
+        "record",
+        "Record the races shown to FILE in the asciicast v2 format, overwriting it. Pauses between races are shortened to at most a second.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "stats",
+        "Append statistics about each generation to FILE in CSV format: the spread of scores and times, the diversity of the population, the mutation rate, and the seconds since the start.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "checkpoint",
+        "Periodically save the whole evolution state to FILE, and also save it and exit on an interrupt.",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "checkpoint-every",
+        "Set how many generations pass between checkpoints. This is a positive integer. The default is 10.",
+        "COUNT",
+    );
+    opts.optopt(
+        "",
+        "resume",
+        "Resume the evolution saved in the checkpoint FILE with the options it was started with. No other options may be given.",
+        "FILE",
+    );
     opts
 }
 
 fn print_help(opts: &Options) -> String {
-    let name = env::args().nth(0).unwrap_or("(anonymous)".to_string());
+    let name = env::args()
+        .next()
+        .unwrap_or_else(|| "(anonymous)".to_string());
     format!(
         "{}\n\n{}\n",
         opts.short_usage(&name),
@@ -75,7 +313,8 @@ fn print_help(opts: &Options) -> String {
 
 fn main() {
     let opts = options();
-    let matches = opts.parse(env::args()).unwrap_or_else(|err| {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let mut matches = opts.parse(&args).unwrap_or_else(|err| {
         eprint!("{}\n\n{}", err, print_help(&opts));
         process::exit(1)
     });
@@ -86,9 +325,36 @@ fn main() {
         println!("vec-rac version 0.4.2");
         process::exit(0);
     }
+    let resumed = matches.opt_str("resume").map(|path| {
+        if args.iter().filter(|arg| arg.starts_with('-')).count() > 1 {
+            eprintln!("No options may be given along with --resume.");
+            process::exit(1)
+        }
+        let checkpoint = Checkpoint::load(&path).unwrap_or_else(|err| {
+            eprintln!("Cannot resume from {}: {}", path, err);
+            process::exit(1)
+        });
+        args = checkpoint.args.clone();
+        matches = opts.parse(&args).unwrap_or_else(|err| {
+            eprintln!("Checkpoint {} has bad options: {}", path, err);
+            process::exit(1)
+        });
+        checkpoint
+    });
+    let loaded = match &resumed {
+        Some(checkpoint) => checkpoint.brains.first().cloned(),
+        None => matches.opt_str("load").map(|path| {
+            Brain::load(&path).unwrap_or_else(|err| {
+                eprintln!("Cannot load racer from {}: {}", path, err);
+                process::exit(1)
+            })
+        }),
+    };
+    let save_path = matches.opt_str("save");
     let view_dist = matches
         .opt_str("view-dist")
-        .and_then(|arg| i32::from_str(&arg).ok());
+        .and_then(|arg| i32::from_str(&arg).ok())
+        .or_else(|| loaded.as_ref().map(Brain::view_dist));
     let display_dist = matches
         .opt_str("display-dist")
         .and_then(|arg| i32::from_str(&arg).ok());
@@ -104,146 +370,865 @@ fn main() {
         .opt_str("path-radius")
         .and_then(|arg| i32::from_str(&arg).ok())
         .unwrap_or(4);
+    let min_path_radius = matches
+        .opt_str("min-path-radius")
+        .and_then(|arg| i32::from_str(&arg).ok())
+        .unwrap_or(path_radius);
+    let width_rate = matches
+        .opt_str("width-rate")
+        .and_then(|arg| i32::from_str(&arg).ok())
+        .unwrap_or(4);
+    let obstacle_density = matches
+        .opt_str("obstacle-density")
+        .and_then(|arg| f64::from_str(&arg).ok())
+        .unwrap_or(0.0);
+    let finish_dist = matches
+        .opt_str("finish-dist")
+        .and_then(|arg| i32::from_str(&arg).ok());
+    let track_shape = matches
+        .opt_str("track-shape")
+        .map(|arg| {
+            TrackShape::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(TrackShape::Corridor);
+    let track_style = matches
+        .opt_str("track-style")
+        .map(|arg| {
+            TrackStyle::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(TrackStyle::Walk);
+    let laps = matches
+        .opt_str("laps")
+        .and_then(|arg| u32::from_str(&arg).ok())
+        .map(|laps| u32::max(1, laps))
+        .unwrap_or(3);
     let seed = matches
         .opt_str("seed")
         .and_then(|arg| u64::from_str(&arg).ok())
         .unwrap_or_else(|| {
             // Seed the RNG from the system time now.
-            SystemTime::now()
+            let seed = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .map(|d| d.as_secs())
-                .unwrap_or(0)
+                .unwrap_or(0);
+            // Remember the seed so a resumed run uses the same one.
+            args.push(format!("--seed={}", seed));
+            seed
         });
+    let train_seeds = matches
+        .opt_str("train-seeds")
+        .and_then(|arg| u64::from_str(&arg).ok())
+        .map(|count| u64::max(1, count))
+        .unwrap_or(1);
+    let resample_seeds = matches.opt_present("resample-seeds");
+    let aggregate = matches
+        .opt_str("aggregate")
+        .map(|arg| {
+            Aggregate::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(Aggregate::Mean);
+    let validation_seeds = matches
+        .opt_str("validation-seeds")
+        .and_then(|arg| u64::from_str(&arg).ok())
+        .unwrap_or(0);
+    let validate_every = matches
+        .opt_str("validate-every")
+        .and_then(|arg| u64::from_str(&arg).ok())
+        .map(|every| u64::max(1, every))
+        .unwrap_or(10);
+    let champion_by_validation = match matches.opt_str("champion-by").as_deref() {
+        None | Some("training") => false,
+        Some("validation") if validation_seeds > 0 => true,
+        Some("validation") => {
+            eprintln!("Choosing the champion by validation score needs validation seeds.");
+            process::exit(1)
+        }
+        Some(arg) => {
+            eprintln!("Invalid champion choice: {}", arg);
+            process::exit(1)
+        }
+    };
+    let traversal = matches
+        .opt_str("collision")
+        .map(|arg| {
+            Traversal::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or_default();
     let population = matches
         .opt_str("population")
         .and_then(|arg| usize::from_str(&arg).ok())
-        .map(|pop| {
-            if pop == 0 {
-                2
-            } else if pop & 1 == 1 {
-                pop + 1
-            } else {
-                pop
-            }
-        })
+        .map(|pop| usize::max(1, pop))
         .unwrap_or(10);
+    let selection = matches
+        .opt_str("selection")
+        .map(|arg| {
+            Selection::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(Selection::Truncation);
+    let elitism = matches
+        .opt_str("elitism")
+        .and_then(|arg| usize::from_str(&arg).ok())
+        .map(|count| usize::min(count, population))
+        .unwrap_or(population / 2);
+    let sensors = matches
+        .opt_str("sensors")
+        .map(|arg| {
+            Sensors::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(Sensors::Grid);
+    let actions = matches
+        .opt_str("actions")
+        .map(|arg| {
+            Actions::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(Actions::Cardinal);
+    let layers = matches
+        .opt_str("layers")
+        .map(|arg| {
+            arg.split(',')
+                .filter(|size| !size.is_empty())
+                .map(|size| match usize::from_str(size) {
+                    Ok(size) if size > 0 => size,
+                    _ => {
+                        eprintln!("Invalid layer size: {}", size);
+                        process::exit(1)
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|| vec![32]);
+    let activations = matches
+        .opt_str("activation")
+        .map(|arg| {
+            arg.split(',')
+                .map(|name| {
+                    Activation::from_str(name).unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        process::exit(1)
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_else(|| vec![Activation::Tanh]);
+    let layers = if activations.len() == 1 {
+        layers
+            .iter()
+            .map(|&size| HiddenLayer::new(size, activations[0]))
+            .collect::<Vec<_>>()
+    } else if activations.len() == layers.len() {
+        layers
+            .iter()
+            .zip(activations)
+            .map(|(&size, activation)| HiddenLayer::new(size, activation))
+            .collect::<Vec<_>>()
+    } else {
+        eprintln!(
+            "There are {} activation functions for {} hidden layers.",
+            activations.len(),
+            layers.len()
+        );
+        process::exit(1)
+    };
     let mutation = matches
         .opt_str("mutation")
         .and_then(|arg| f64::from_str(&arg).ok())
         .unwrap_or(0.05);
-    matches
+    let crossover_rate = matches
+        .opt_str("crossover-rate")
+        .and_then(|arg| f64::from_str(&arg).ok())
+        .unwrap_or(0.0);
+    let crossover = matches
+        .opt_str("crossover")
+        .map(|arg| {
+            Crossover::from_str(&arg).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(1)
+            })
+        })
+        .unwrap_or(Crossover::Uniform);
+    let max_generations = matches
+        .opt_str("max-generations")
+        .and_then(|arg| u64::from_str(&arg).ok());
+    let target_score = matches
+        .opt_str("target-score")
+        .and_then(|arg| f64::from_str(&arg).ok());
+    let time_limit = matches.opt_str("time-limit").and_then(|arg| {
+        let secs = f64::from_str(&arg).ok()?;
+        Some(
+            Duration::try_from_secs_f64(f64::max(0.0, secs)).unwrap_or_else(|_| {
+                eprintln!("Invalid time limit: {} seconds is too long.", arg);
+                process::exit(1)
+            }),
+        )
+    });
+    let stagnation_limit = matches
+        .opt_str("stagnation-limit")
+        .and_then(|arg| u64::from_str(&arg).ok());
+    let mut stats = matches.opt_str("stats").map(|path| {
+        open_stats(&path).unwrap_or_else(|err| {
+            eprintln!("Cannot write statistics to {}: {}", path, err);
+            process::exit(1)
+        })
+    });
+    let checkpoint_path = matches.opt_str("checkpoint");
+    let checkpoint_every = matches
+        .opt_str("checkpoint-every")
+        .and_then(|arg| u64::from_str(&arg).ok())
+        .map(|every| u64::max(1, every))
+        .unwrap_or(10);
+    if let Some(count) = matches
         .opt_str("testing-threads")
         .and_then(|arg| usize::from_str(&arg).ok())
-        .map(|count| {
-            ThreadPoolBuilder::new()
-                .num_threads(count)
-                .build_global()
-                .unwrap()
-        });
+    {
+        ThreadPoolBuilder::new()
+            .num_threads(count)
+            .build_global()
+            .unwrap()
+    }
     let mut rng = Rng::with_seed(seed + 17);
-    let track_builder = Racetrack::builder().path_radius(path_radius).seed(seed);
-    let track = track_builder.clone().view_dist(view_dist).build();
-    let mut brains = iter::repeat_with(|| Brain::random(view_dist, &mut rng))
-        .take(population)
+    let mut track_builder = Racetrack::builder()
+        .path_radius(path_radius)
+        .min_path_radius(min_path_radius)
+        .width_rate(width_rate)
+        .obstacle_density(obstacle_density)
+        .shape(track_shape)
+        .style(track_style)
+        .laps(laps)
+        .seed(seed);
+    if let Some(finish_dist) = finish_dist {
+        track_builder = track_builder.finish_dist(finish_dist);
+    }
+    if let Some(path) = matches.opt_str("track") {
+        let map = TrackMap::load(&path).unwrap_or_else(|err| {
+            eprintln!("Cannot load track from {}: {}", path, err);
+            process::exit(1)
+        });
+        track_builder = track_builder.map(Arc::new(map));
+    }
+    let headless = matches.opt_present("headless");
+    let json_progress = match matches.opt_str("progress-format").as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(arg) => {
+            eprintln!("Invalid progress format: {}", arg);
+            process::exit(1)
+        }
+    };
+    if headless && matches.opt_present("record") {
+        eprintln!("Nothing can be recorded in headless mode.");
+        process::exit(1)
+    }
+    let (screen_width, screen_height) = screen_size(display_dist);
+    let screen = if headless {
+        None
+    } else {
+        Some(Screen::new(
+            matches.opt_str("record").as_deref(),
+            screen_width,
+            screen_height,
+        ))
+    };
+    if let Some(driver) = matches.opt_str("drive") {
+        let track = track_builder.view_dist(display_dist).build();
+        let mut driver: Box<dyn Driver> = match driver.as_str() {
+            "greedy" => Box::new(GreedyDriver::with_traversal(3, traversal)),
+            "human" => Box::new(HumanDriver::new(io::stdin().lock())),
+            path => Box::new(Brain::load(path).unwrap_or_else(|err| {
+                eprintln!("Cannot load racer from {}: {}", path, err);
+                process::exit(1)
+            })),
+        };
+        match screen {
+            Some(mut screen) => show_race(&mut *driver, &track, traversal, &mut screen),
+            None => {
+                let outcome = Race::with_traversal(&track, traversal).run(&mut *driver);
+                let end = match outcome.end {
+                    EndCause::Crash => "crashed",
+                    EndCause::Stagnation => "stagnated",
+                    EndCause::Finish => "finished",
+                };
+                if json_progress {
+                    println!(
+                        "{{\"score\": {}, \"time\": {}, \"end\": \"{}\"}}",
+                        outcome.score, outcome.time, end
+                    );
+                } else {
+                    println!("score {}  time {}  {}", outcome.score, outcome.time, end);
+                }
+            }
+        }
+        process::exit(0);
+    }
+    let mut brains = match loaded {
+        Some(brain) => {
+            let mut brains = iter::repeat_with(|| brain.mutant(&mut rng, mutation))
+                .take(population - 1)
+                .collect::<Vec<_>>();
+            brains.insert(0, brain);
+            brains
+        }
+        None => {
+            let brain_builder = Brain::builder()
+                .view_dist(view_dist)
+                .sensors(sensors)
+                .actions(actions)
+                .hidden_layers(layers);
+            iter::repeat_with(|| brain_builder.clone().random(&mut rng))
+                .take(population)
+                .collect::<Vec<_>>()
+        }
+    };
+    let mut best: Option<(Brain, Fitness)> = None;
+    let mut generation = 0;
+    let mut improved_at = 0;
+    if let Some(checkpoint) = resumed {
+        brains = checkpoint.brains;
+        rng = checkpoint.rng;
+        best = checkpoint.best;
+        generation = checkpoint.generation;
+        improved_at = checkpoint.improved_at;
+    }
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if checkpoint_path.is_some() {
+        // A second interrupt kills the program without waiting for a checkpoint.
+        signal_hook::flag::register_conditional_shutdown(SIGINT, 130, Arc::clone(&interrupted))
+            .and_then(|_| signal_hook::flag::register(SIGINT, Arc::clone(&interrupted)))
+            .unwrap_or_else(|err| {
+                eprintln!("Cannot handle interrupts: {}", err);
+                process::exit(1)
+            });
+    }
+    let (tx, replay) = match screen {
+        Some(mut screen) => {
+            let (tx, rx) = mpsc::channel();
+            let displayed_track = track_builder.clone().view_dist(display_dist).build();
+            let replay = thread::spawn(move || {
+                for mut brain in rx {
+                    screen.show("\x07", 0.0);
+                    show_race(&mut brain, &displayed_track, traversal, &mut screen);
+                }
+            });
+            (Some(tx), Some(replay))
+        }
+        None => (None, None),
+    };
+    let start = Instant::now();
+    let build_tracks = |generation| {
+        let first = seed.wrapping_add(generation * train_seeds);
+        (0..train_seeds)
+            .map(|i| {
+                track_builder
+                    .clone()
+                    .seed(first.wrapping_add(i))
+                    .view_dist(view_dist)
+                    .build()
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut tracks = build_tracks(0);
+    let validation_tracks = (1..=validation_seeds)
+        .map(|i| {
+            track_builder
+                .clone()
+                .seed(seed.wrapping_sub(i))
+                .view_dist(view_dist)
+                .build()
+        })
         .collect::<Vec<_>>();
-    let mut max_max_score = 0;
-    let (tx, rx) = mpsc::channel();
-    thread::spawn(move || {
-        let displayed_track = track_builder.view_dist(display_dist).build();
-        for brain in rx {
-            print!("\x07");
-            test_brain(&brain, &displayed_track, true);
+    let stop = loop {
+        if resample_seeds {
+            tracks = build_tracks(generation);
         }
-    });
-    loop {
         let mut results = brains
             .par_iter()
-            .map(|brain| (brain.clone(), test_brain(brain, &track, false)))
+            .map(|brain| {
+                (
+                    brain.clone(),
+                    evaluate(brain, &tracks, aggregate, traversal),
+                )
+            })
             .collect::<Vec<_>>();
-        results.sort_by(|(_, (score_a, time_a)), (_, (score_b, time_b))| {
-            score_b.cmp(&score_a).then(time_b.cmp(&time_a))
-        });
-        results.truncate(population / 2);
-        let max_score = (results[0].1).0;
-        if max_score > max_max_score {
-            max_max_score = max_score;
-            tx.send(results[0].0.clone()).unwrap();
+        results.sort_by(|(_, a), (_, b)| a.rank(b));
+        let validation = if validation_seeds > 0 && (generation + 1) % validate_every == 0 {
+            let validation = evaluate(&results[0].0, &validation_tracks, aggregate, traversal);
+            if !headless {
+                eprintln!(
+                    "generation {}: training score {}, validation score {}",
+                    generation + 1,
+                    results[0].1.score,
+                    validation.score
+                );
+            }
+            Some(validation)
+        } else {
+            None
+        };
+        if headless {
+            let fitnesses = results
+                .iter()
+                .map(|(_, fitness)| *fitness)
+                .collect::<Vec<_>>();
+            print_progress(
+                json_progress,
+                generation + 1,
+                &fitnesses,
+                validation,
+                start.elapsed(),
+            );
+        }
+        if let Some(out) = &mut stats {
+            if let Err(err) = write_stats(out, generation + 1, &results, mutation, start.elapsed())
+            {
+                eprintln!("Cannot write statistics: {}", err);
+                stats = None;
+            }
         }
+        let champion = if champion_by_validation {
+            validation
+        } else {
+            Some(results[0].1)
+        };
+        if let Some(fitness) = champion.filter(|&fitness| is_new_best(fitness, best.as_ref())) {
+            improved_at = generation + 1;
+            if let Some(path) = &save_path {
+                if let Err(err) = results[0].0.save(path) {
+                    eprintln!("Cannot save racer to {}: {}", path, err);
+                }
+            }
+            best = Some((results[0].0.clone(), fitness));
+            if let Some(tx) = &tx {
+                tx.send(results[0].0.clone()).unwrap();
+            }
+        }
+        let fitnesses = results
+            .iter()
+            .map(|(_, fitness)| fitness.score)
+            .collect::<Vec<_>>();
+        let ranked = results
+            .into_iter()
+            .map(|(brain, _)| brain)
+            .collect::<Vec<_>>();
         brains.clear();
-        for (brain, _) in results.into_iter() {
-            brains.push(brain.mutant(&mut rng, mutation));
-            brains.push(brain);
+        brains.extend(ranked.iter().take(elitism).cloned());
+        let mut turn = 0;
+        while brains.len() < population {
+            let parent = &ranked[selection.pick(&fitnesses, turn, &mut rng)];
+            turn += 1;
+            // The RNG is only used for crossover when it is enabled so that runs
+            // without it stay the same.
+            let child = if crossover_rate > 0.0 && rng.fraction() < crossover_rate {
+                let partner = &ranked[selection.pick(&fitnesses, turn, &mut rng)];
+                turn += 1;
+                Brain::crossover(parent, partner, &mut rng, crossover).mutant(&mut rng, mutation)
+            } else {
+                parent.mutant(&mut rng, mutation)
+            };
+            brains.push(child);
+        }
+        generation += 1;
+        let stop = if max_generations.filter(|&max| generation >= max).is_some() {
+            Some(StopReason::MaxGenerations)
+        } else if target_score
+            .filter(|&target| best_score(best.as_ref()) >= target)
+            .is_some()
+        {
+            Some(StopReason::TargetScore)
+        } else if time_limit
+            .filter(|&limit| start.elapsed() >= limit)
+            .is_some()
+        {
+            Some(StopReason::TimeLimit)
+        } else if stagnation_limit
+            .filter(|&limit| generation - improved_at >= limit)
+            .is_some()
+        {
+            Some(StopReason::Stagnation)
+        } else {
+            None
+        };
+        if let Some(path) = &checkpoint_path {
+            let interrupt = interrupted.load(Ordering::SeqCst);
+            if interrupt || stop.is_some() || generation % checkpoint_every == 0 {
+                let checkpoint = Checkpoint {
+                    args: args.clone(),
+                    generation,
+                    rng: rng.clone(),
+                    improved_at,
+                    best: best.clone(),
+                    brains: brains.clone(),
+                };
+                if let Err(err) = checkpoint.save(path) {
+                    eprintln!("Cannot save checkpoint to {}: {}", path, err);
+                }
+            }
+            if interrupt {
+                process::exit(130);
+            }
+        }
+        if let Some(stop) = stop {
+            break stop;
+        }
+    };
+    if let (Some(path), Some((best, _))) = (&save_path, &best) {
+        if let Err(err) = best.save(path) {
+            eprintln!("Cannot save racer to {}: {}", path, err);
+        }
+    }
+    // Let the last replay finish before summing up.
+    drop(tx);
+    if let Some(replay) = replay {
+        replay.join().ok();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    if json_progress {
+        println!(
+            "{{\"stopped\": \"{}\", \"generations\": {}, \"best_score\": {}, \"elapsed\": {:.3}}}",
+            stop,
+            generation,
+            best_score(best.as_ref()),
+            elapsed
+        );
+    } else {
+        println!(
+            "Stopped by {} after {} generations and {:.1}s. Best score: {}",
+            stop,
+            generation,
+            elapsed,
+            best_score(best.as_ref())
+        );
+    }
+}
+
+// Whether the fitness ranks ahead of that of the best racer so far. Among
+// racers with the top score, one that finishes sooner is a new best.
+fn is_new_best(fitness: Fitness, best: Option<&(Brain, Fitness)>) -> bool {
+    match best {
+        Some((_, best)) => fitness.rank(best) == cmp::Ordering::Less,
+        None => fitness.score > 0.0,
+    }
+}
+
+fn best_score(best: Option<&(Brain, Fitness)>) -> f64 {
+    match best {
+        Some((_, fitness)) => fitness.score,
+        None => 0.0,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StopReason {
+    MaxGenerations,
+    TargetScore,
+    TimeLimit,
+    Stagnation,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StopReason::MaxGenerations => "max-generations",
+            StopReason::TargetScore => "target-score",
+            StopReason::TimeLimit => "time-limit",
+            StopReason::Stagnation => "stagnation-limit",
+        })
+    }
+}
+
+// Shows frames on the terminal, and records them if asked.
+struct Screen {
+    recorder: Option<Recorder<BufWriter<File>>>,
+    // When the last race shown ended.
+    idle_since: Instant,
+}
+
+impl Screen {
+    fn new(record_path: Option<&str>, width: usize, height: usize) -> Screen {
+        let recorder = record_path.map(|path| {
+            File::create(path)
+                .and_then(|file| Recorder::new(BufWriter::new(file), width, height, 1.0))
+                .unwrap_or_else(|err| {
+                    eprintln!("Cannot record to {}: {}", path, err);
+                    process::exit(1)
+                })
+        });
+        Screen {
+            recorder,
+            idle_since: Instant::now(),
         }
     }
+
+    // Shows the text for the given number of seconds.
+    fn show(&mut self, text: &str, secs: f64) {
+        print!("{}", text);
+        io::stdout().flush().ok();
+        if let Some(recorder) = &mut self.recorder {
+            // The terminal would turn each newline into a carriage return and a
+            // newline.
+            if let Err(err) = recorder.output(&text.replace('\n', "\r\n")) {
+                eprintln!("Cannot record: {}", err);
+                self.recorder = None;
+            } else {
+                recorder.wait(secs);
+            }
+        }
+        thread::sleep(Duration::from_secs_f64(secs));
+    }
+
+    fn start_race(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.idle(self.idle_since.elapsed().as_secs_f64());
+        }
+    }
+
+    fn end_race(&mut self) {
+        self.idle_since = Instant::now();
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(err) = recorder.flush() {
+                eprintln!("Cannot record: {}", err);
+                self.recorder = None;
+            }
+        }
+    }
+}
+
+// Status lines are cut to this many characters, so they fit in a recording.
+const STATUS_WIDTH: usize = 40;
+
+// The size of terminal that frames fill: the rows of the track, a status line,
+// and the row the cursor is left on.
+fn screen_size(display_dist: i32) -> (usize, usize) {
+    let view_width = (display_dist * 2 + 1) as usize;
+    (usize::max(view_width, STATUS_WIDTH), view_width + 2)
 }
 
-fn clear_terminal() {
-    print!("\x1b[H\x1b[J");
+fn draw_frame(track: &Racetrack, status: &str) -> String {
+    let mut frame = draw_track(track);
+    frame.extend(status.chars().take(STATUS_WIDTH));
+    frame.push('\n');
+    frame
 }
 
-fn draw_track(track: &Racetrack) {
+fn draw_track(track: &Racetrack) -> String {
     let view_dist = track.view_dist();
-    clear_terminal();
+    let mut frame = String::from("\x1b[H\x1b[J");
     for y in (-view_dist..=view_dist).rev() {
         for x in -view_dist..=view_dist {
             let pos = Vector::new(x, y);
-            let c = if pos == Vector::ORIGIN {
+            frame.push(if pos == Vector::ORIGIN {
                 '@'
-            } else if let Some(true) = track.get(pos) {
-                '.'
-            } else {
+            } else if track.get(pos) != Some(true) {
                 ' '
-            };
-            print!("{}", c);
-        }
-        print!("\n");
-    }
-}
-
-fn test_brain(brain: &Brain, track: &Racetrack, show: bool) -> (i32, usize) {
-    let mut track = track.clone();
-    let mut time = 0usize;
-    let mut vel = Vector::ORIGIN;
-    let mut pos = Vector::ORIGIN;
-    let mut max_score = 0;
-    let mut since_improved = 0;
-    track.translate(Vector::ORIGIN);
-    'tick_loop: loop {
-        vel = vel + brain.compute_accel(vel, &track);
-        for pt in Vector::ORIGIN.segment_pts(vel) {
-            if let Some(false) = track.get(pt) {
-                if show {
-                    track.translate(pt);
-                } else {
-                    pos = pos + pt;
-                }
-                break 'tick_loop;
-            }
+            } else if track.is_finish(pos) {
+                '='
+            } else {
+                '.'
+            });
         }
-        pos = pos + vel;
-        if pos.y > max_score {
-            max_score = pos.y;
-            since_improved = 0;
-        } else if since_improved > 50 {
-            break 'tick_loop;
-        } else {
-            since_improved += 1;
+        frame.push('\n');
+    }
+    frame
+}
+
+// The fitnesses must be sorted from best to worst.
+fn print_progress(
+    json: bool,
+    generation: u64,
+    fitnesses: &[Fitness],
+    validation: Option<Fitness>,
+    elapsed: Duration,
+) {
+    let mut scores = fitnesses
+        .iter()
+        .map(|fitness| fitness.score)
+        .collect::<Vec<_>>();
+    let best = fitnesses[0];
+    let mean = fitness::mean(&scores);
+    let median = fitness::median(&mut scores);
+    let elapsed = elapsed.as_secs_f64();
+    if json {
+        print!(
+            "{{\"generation\": {}, \"best_score\": {}, \"mean_score\": {}, \"median_score\": {}, \"best_time\": {}, \"elapsed\": {:.3}",
+            generation, best.score, mean, median, best.time, elapsed
+        );
+        if let Some(validation) = validation {
+            print!(", \"validation_score\": {}", validation.score);
         }
-        track.translate(vel);
-        time = time.saturating_add(1);
-        if show {
-            draw_track(&track);
-            println!("score: {}  velocity: {}", pos.y, vel);
-            thread::sleep(Duration::from_millis(50));
+        println!("}}");
+    } else {
+        print!(
+            "generation {}  best {}  mean {:.2}  median {}  best time {}  elapsed {:.1}s",
+            generation, best.score, mean, median, best.time, elapsed
+        );
+        if let Some(validation) = validation {
+            print!("  validation {}", validation.score);
         }
-        if let Some(false) = track.get(Vector::ORIGIN) {
-            break 'tick_loop;
+        println!();
+    }
+}
+
+const STATS_HEADER: &str = "generation,score_min,score_mean,score_max,score_stddev,time_min,time_mean,time_max,time_stddev,diversity,mutation,elapsed";
+
+// Opens the file for appending, and starts it with a header if it is new.
+fn open_stats(path: &str) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut out = BufWriter::new(file);
+    if is_new {
+        writeln!(out, "{}", STATS_HEADER)?;
+    }
+    Ok(out)
+}
+
+fn write_stats<W: Write>(
+    out: &mut W,
+    generation: u64,
+    results: &[(Brain, Fitness)],
+    mutation: f64,
+    elapsed: Duration,
+) -> io::Result<()> {
+    let scores = results
+        .iter()
+        .map(|(_, fitness)| fitness.score)
+        .collect::<Vec<_>>();
+    let times = results
+        .iter()
+        .map(|(_, fitness)| fitness.time)
+        .collect::<Vec<_>>();
+    let score = Summary::of(&scores);
+    let time = Summary::of(&times);
+    // The mean distance between the brains of every pair.
+    let distances = results
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (a, _))| results[i + 1..].iter().map(move |(b, _)| a.distance(b)))
+        .collect::<Vec<_>>();
+    let diversity = if distances.is_empty() {
+        0.0
+    } else {
+        fitness::mean(&distances)
+    };
+    writeln!(
+        out,
+        "{},{},{},{},{},{},{},{},{},{},{},{:.3}",
+        generation,
+        score.min,
+        score.mean,
+        score.max,
+        score.stddev,
+        time.min,
+        time.mean,
+        time.max,
+        time.stddev,
+        diversity,
+        mutation,
+        elapsed.as_secs_f64()
+    )?;
+    out.flush()
+}
+
+fn evaluate(
+    brain: &Brain,
+    tracks: &[Racetrack],
+    aggregate: Aggregate,
+    traversal: Traversal,
+) -> Fitness {
+    let mut brain = brain.clone();
+    let outcomes = tracks
+        .iter()
+        .map(|track| Race::with_traversal(track, traversal).run(&mut brain))
+        .collect::<Vec<_>>();
+    aggregate.apply(&outcomes)
+}
+
+fn show_race<D: Driver + ?Sized>(
+    driver: &mut D,
+    track: &Racetrack,
+    traversal: Traversal,
+    screen: &mut Screen,
+) {
+    screen.start_race();
+    let mut race = Race::with_traversal(track, traversal);
+    let outcome = loop {
+        if let Some(outcome) = race.step(driver) {
+            break outcome;
+        }
+        let status = format!("score: {}  velocity: {}", race.score(), race.vel());
+        screen.show(&draw_frame(race.track(), &status), 0.05);
+    };
+    let status = format!("max score: {}", outcome.score);
+    screen.show(&draw_frame(race.track(), &status), 0.15);
+    screen.end_race();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec_rac::asciicast;
+    use vec_rac::race::RaceOutcome;
+
+    #[test]
+    fn frames_fit_the_recording() {
+        for &display_dist in [1, 3, 20].iter() {
+            let track = Racetrack::builder().view_dist(display_dist).seed(1).build();
+            let status = format!(
+                "score: {}  velocity: {}",
+                -123_456_789,
+                Vector::new(-1_000_000, 1_000_000)
+            );
+            let (width, height) = asciicast::frame_size(&draw_frame(&track, &status));
+            let (screen_width, screen_height) = screen_size(display_dist);
+            assert!(width <= screen_width, "{}", display_dist);
+            assert_eq!(height, screen_height);
         }
     }
-    if show {
-        draw_track(&track);
-        println!("max score: {}", pos.y);
-        thread::sleep(Duration::from_millis(150));
+
+    #[test]
+    fn faster_finisher_becomes_the_best() {
+        let fitness = |time, end| {
+            Aggregate::Mean.apply(&[RaceOutcome {
+                score: 30,
+                time,
+                end,
+                crash_point: None,
+            }])
+        };
+        let brain = Brain::builder().view_dist(4).random(&mut Rng::with_seed(1));
+        let best = (brain, fitness(50, EndCause::Finish));
+        assert!(is_new_best(fitness(40, EndCause::Finish), Some(&best)));
+        assert!(!is_new_best(fitness(50, EndCause::Finish), Some(&best)));
+        assert!(!is_new_best(fitness(60, EndCause::Finish), Some(&best)));
+        assert!(!is_new_best(fitness(90, EndCause::Crash), Some(&best)));
+        // Finishing beats getting as far without finishing.
+        let best = (best.0, fitness(90, EndCause::Crash));
+        assert!(is_new_best(fitness(60, EndCause::Finish), Some(&best)));
+        // The first best racer must have gotten somewhere.
+        assert!(is_new_best(fitness(90, EndCause::Crash), None));
+        let stuck = Fitness {
+            score: 0.0,
+            time: 90.0,
+            finished: false,
+        };
+        assert!(!is_new_best(stuck, None));
     }
-    (pos.y, time)
 }
@@ -0,0 +1,325 @@
+use crate::vector::Vector;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A track drawn by hand, read from a text file like this one:
+//
+//     velocity 1 0
+//     ##########
+//     #S     FF#
+//     ##########
+//
+// Each character of the drawing is a cell. North is at the top. '#' is a wall,
+// ' ' and '.' are open, 'S' is the start, and 'F' is part of the finish line.
+// Cells past the end of a line and outside the drawing are walls. Lines before
+// the drawing that start with a lowercase letter are settings. The only setting
+// is the start velocity, with x east and y north, which is 0 0 by default.
+//
+// Positions are absolute, with the south-west corner of the drawing at the
+// origin.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct TrackMap {
+    width: i32,
+    height: i32,
+    cells: Vec<bool>,
+    start: Vector,
+    start_vel: Vector,
+    // How many king's moves each open cell is from the finish line, if the
+    // map has one and the cell can reach it. Walls next to such cells are one
+    // move further than them, so a racer that crashes still gets a distance.
+    finish_dists: Option<Vec<Option<u32>>>,
+}
+
+impl TrackMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TrackMap, MapError> {
+        TrackMap::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<TrackMap, MapError> {
+        let mut lines = text.lines().enumerate().peekable();
+        let mut start_vel = Vector::ORIGIN;
+        while let Some((i, line)) =
+            lines.next_if(|(_, line)| line.starts_with(|c: char| c.is_ascii_lowercase()))
+        {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            start_vel = match words[..] {
+                ["velocity", x, y] => match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => Vector::new(x, y),
+                    _ => return Err(MapError::BadSetting(i + 1)),
+                },
+                _ => return Err(MapError::BadSetting(i + 1)),
+            };
+        }
+        let mut rows = lines.collect::<Vec<_>>();
+        while let Some((_, line)) = rows.last() {
+            if line.trim().is_empty() {
+                rows.pop();
+            } else {
+                break;
+            }
+        }
+        let width = rows
+            .iter()
+            .map(|(_, line)| line.chars().count())
+            .max()
+            .unwrap_or(0) as i32;
+        let height = rows.len() as i32;
+        if width == 0 {
+            return Err(MapError::Empty);
+        }
+        let mut cells = vec![false; (width * height) as usize];
+        let mut start: Option<(Vector, usize, usize)> = None;
+        let mut finish = Vec::new();
+        for (row, &(i, line)) in rows.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let pos = Vector::new(column as i32, height - 1 - row as i32);
+                let open = match c {
+                    '#' => false,
+                    ' ' | '.' => true,
+                    'S' => {
+                        if let Some((_, first_line, first_column)) = start {
+                            return Err(MapError::TwoStarts {
+                                first: (first_line, first_column),
+                                second: (i + 1, column + 1),
+                            });
+                        }
+                        start = Some((pos, i + 1, column + 1));
+                        true
+                    }
+                    'F' => {
+                        finish.push(pos);
+                        true
+                    }
+                    _ => return Err(MapError::BadCell(i + 1, column + 1, c)),
+                };
+                cells[(pos.x + pos.y * width) as usize] = open;
+            }
+        }
+        let start = match start {
+            Some((pos, _, _)) => pos,
+            None => return Err(MapError::NoStart),
+        };
+        let mut map = TrackMap {
+            width,
+            height,
+            cells,
+            start,
+            start_vel,
+            finish_dists: None,
+        };
+        if !finish.is_empty() {
+            let dists = map.dists_from(&finish);
+            if dists[map.index(start).unwrap()].is_none() {
+                return Err(MapError::UnreachableFinish);
+            }
+            map.finish_dists = Some(dists);
+        }
+        Ok(map)
+    }
+
+    // Finds how many king's moves each open cell is from the nearest source,
+    // and walls from the nearest such cell.
+    fn dists_from(&self, sources: &[Vector]) -> Vec<Option<u32>> {
+        let mut dists = vec![None; self.cells.len()];
+        let mut queue = VecDeque::new();
+        for &pos in sources {
+            dists[self.index(pos).unwrap()] = Some(0);
+            queue.push_back(pos);
+        }
+        while let Some(pos) = queue.pop_front() {
+            let dist = dists[self.index(pos).unwrap()].unwrap();
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let next = pos + Vector::new(x, y);
+                    if let Some(i) = self.index(next) {
+                        if dists[i].is_none() {
+                            dists[i] = Some(dist + 1);
+                            if self.cells[i] {
+                                queue.push_back(next);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        dists
+    }
+
+    pub fn is_open(&self, pos: Vector) -> bool {
+        match self.index(pos) {
+            Some(i) => self.cells[i],
+            None => false,
+        }
+    }
+
+    fn index(&self, pos: Vector) -> Option<usize> {
+        if (0..self.width).contains(&pos.x) && (0..self.height).contains(&pos.y) {
+            Some((pos.x + pos.y * self.width) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn start(&self) -> Vector {
+        self.start
+    }
+
+    pub fn start_vel(&self) -> Vector {
+        self.start_vel
+    }
+
+    pub fn has_finish(&self) -> bool {
+        self.finish_dists.is_some()
+    }
+
+    pub fn is_finish(&self, pos: Vector) -> bool {
+        self.finish_dist(pos) == Some(0)
+    }
+
+    // How far the position is from the finish line, in king's moves along open
+    // cells. Positions outside the drawing have none.
+    pub fn finish_dist(&self, pos: Vector) -> Option<u32> {
+        match (&self.finish_dists, self.index(pos)) {
+            (Some(dists), Some(i)) => dists[i],
+            _ => None,
+        }
+    }
+
+    // How much closer to the finish line the position is than the start. This
+    // is 0 where the finish line cannot be reached.
+    pub fn progress(&self, pos: Vector) -> Option<i32> {
+        let start = self.finish_dist(self.start)?;
+        Some(match self.finish_dist(pos) {
+            Some(dist) => start as i32 - dist as i32,
+            None => 0,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Empty,
+    // The line of an invalid setting.
+    BadSetting(usize),
+    // The line, column, and character of an unknown cell.
+    BadCell(usize, usize, char),
+    NoStart,
+    // The lines and columns of the first two starts.
+    TwoStarts {
+        first: (usize, usize),
+        second: (usize, usize),
+    },
+    UnreachableFinish,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(err) => write!(f, "{}", err),
+            MapError::Empty => write!(f, "track file has no cells"),
+            MapError::BadSetting(line) => write!(
+                f,
+                "track file has an invalid setting on line {}; expected \"velocity X Y\"",
+                line
+            ),
+            MapError::BadCell(line, column, c) => write!(
+                f,
+                "track file has unknown cell {:?} on line {}, column {}; expected '#', ' ', '.', 'S', or 'F'",
+                c, line, column
+            ),
+            MapError::NoStart => write!(f, "track file has no start marked with 'S'"),
+            MapError::TwoStarts { first, second } => write!(
+                f,
+                "track file has two starts, on line {}, column {} and line {}, column {}",
+                first.0, first.1, second.0, second.1
+            ),
+            MapError::UnreachableFinish => write!(
+                f,
+                "track file has a finish line that cannot be reached from the start"
+            ),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MapError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(err: io::Error) -> MapError {
+        MapError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+velocity 2 -1
+#########
+#S  #  F#
+#.. # .F#
+#       #
+#########
+";
+
+    #[test]
+    fn parses_map() {
+        let map = TrackMap::parse(MAP).unwrap();
+        assert_eq!(map.start(), Vector::new(1, 3));
+        assert_eq!(map.start_vel(), Vector::new(2, -1));
+        assert!(map.is_open(Vector::new(2, 2)));
+        assert!(!map.is_open(Vector::new(4, 3)));
+        assert!(!map.is_open(Vector::new(-1, 3)));
+        assert!(!map.is_open(Vector::new(1, 5)));
+        assert!(map.is_finish(Vector::new(7, 2)));
+        // Around the wall in the middle.
+        assert_eq!(map.finish_dist(map.start()), Some(6));
+        assert_eq!(map.progress(map.start()), Some(0));
+        assert_eq!(map.progress(Vector::new(4, 1)), Some(3));
+    }
+
+    #[test]
+    fn finish_is_optional() {
+        let map = TrackMap::parse("S ##\n").unwrap();
+        assert_eq!(map.start(), Vector::ORIGIN);
+        assert_eq!(map.start_vel(), Vector::ORIGIN);
+        assert!(!map.has_finish());
+        assert_eq!(map.progress(map.start()), None);
+    }
+
+    #[test]
+    fn reports_bad_maps() {
+        let error = |text: &str| TrackMap::parse(text).unwrap_err().to_string();
+        assert_eq!(error("\n\n"), "track file has no cells");
+        assert_eq!(
+            error("velocity 1\nS"),
+            "track file has an invalid setting on line 1; expected \"velocity X Y\""
+        );
+        assert_eq!(
+            error("velocity 0 0\n###\n#Sx\n"),
+            "track file has unknown cell 'x' on line 3, column 3; expected '#', ' ', '.', 'S', or 'F'"
+        );
+        assert_eq!(error("# F\n"), "track file has no start marked with 'S'");
+        assert_eq!(
+            error("S \n S\n"),
+            "track file has two starts, on line 1, column 1 and line 2, column 2"
+        );
+        assert_eq!(
+            error("S#F\n"),
+            "track file has a finish line that cannot be reached from the start"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::generator::{derive_seed, hash, OBSTACLES_SALT};
+use crate::rng::Rng;
+use crate::vector::{self, Vector};
+
+// How many cells along the path each obstacle has to itself, so obstacles never
+// pile up to block the path.
+pub const OBSTACLE_SLOT: i32 = 16;
+
+// Pillars, boulders, and islands placed inside a path. Islands follow the path,
+// splitting it into two lanes that rejoin.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Obstacles {
+    density: f64,
+    seed: u64,
+}
+
+impl Obstacles {
+    pub fn none() -> Obstacles {
+        Obstacles::new(0.0, 0)
+    }
+
+    // The density is the chance that each slot has an obstacle.
+    pub fn new(density: f64, seed: u64) -> Obstacles {
+        Obstacles {
+            density: if density > 0.0 { density.min(1.0) } else { 0.0 },
+            seed: derive_seed(seed, OBSTACLES_SALT),
+        }
+    }
+
+    pub fn density(&self) -> f64 {
+        self.density
+    }
+
+    // How far the cells of an obstacle can be from the centers in its slot.
+    pub fn reach(max_radius: i32) -> i32 {
+        max_radius + 2
+    }
+
+    // The cells of the obstacle in the slot, if it has one, given the center
+    // and radius of the path at each cell along it. The slot with the first
+    // cell, where racers start, is always clear.
+    pub fn blocked(
+        &self,
+        slot: i32,
+        center: impl Fn(i32) -> Vector,
+        radius: impl Fn(i32) -> i32,
+    ) -> Vec<Vector> {
+        let mut cells = Vec::new();
+        if slot == 0 || self.density <= 0.0 {
+            return cells;
+        }
+        let mut rng = Rng::with_seed(hash(self.seed, slot as i64) as u64);
+        rng.forward();
+        if rng.fraction() >= self.density {
+            return cells;
+        }
+        let start = slot * OBSTACLE_SLOT;
+        let slot_len = OBSTACLE_SLOT as u64;
+        if rng.below(3) == 0 {
+            // An island leaves a lane of about two cells on each side, so only
+            // fits where the path is wide enough all along.
+            let len = 6 + rng.below(slot_len - 7) as i32;
+            let first = start + rng.below(slot_len - len as u64) as i32;
+            if (first..first + len).all(|n| radius(n) >= 4) {
+                for n in first..first + len {
+                    cells.extend(vector::circle_pts(radius(n) - 3).map(|pt| center(n) + pt));
+                }
+                return cells;
+            }
+        }
+        // A boulder, or a pillar when its radius is 0, leaves the path open at
+        // least on one side.
+        let n = start + 2 + rng.below(slot_len - 4) as i32;
+        let r = radius(n);
+        if r >= 2 {
+            let size = rng.below(i32::min(2, r - 2) as u64 + 1) as i32;
+            let reach = r - size;
+            let offset = rng.below(2 * reach as u64 + 1) as i32 - reach;
+            // Sideways from the direction of the path.
+            let d = center(n + 2) - center(n - 2);
+            let len = f64::hypot(d.x as f64, d.y as f64);
+            let shift = Vector::new(
+                (-d.y as f64 * offset as f64 / len).round() as i32,
+                (d.x as f64 * offset as f64 / len).round() as i32,
+            );
+            cells.extend(vector::circle_pts(size).map(|pt| center(n) + shift + pt));
+        }
+        cells
+    }
+}
+
+// The slot with the nth cell along the path.
+pub fn slot(n: i32) -> i32 {
+    n.div_euclid(OBSTACLE_SLOT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{TrackStyle, WidthProfile};
+    use crate::world::TrackWorld;
+
+    use std::collections::{HashSet, VecDeque};
+
+    // The rows from the start that can be reached by stepping between open
+    // cells on a side.
+    fn reached_rows(world: &TrackWorld, rows: i32) -> (i32, i32) {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(Vector::ORIGIN);
+        queue.push_back(Vector::ORIGIN);
+        let (mut min_y, mut max_y) = (0, 0);
+        while let Some(pos) = queue.pop_front() {
+            min_y = i32::min(min_y, pos.y);
+            max_y = i32::max(max_y, pos.y);
+            for &d in [
+                Vector::new(1, 0),
+                Vector::new(-1, 0),
+                Vector::new(0, 1),
+                Vector::new(0, -1),
+            ]
+            .iter()
+            {
+                let next = pos + d;
+                if i32::abs(next.y) <= rows && world.is_open(next) && seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        (min_y, max_y)
+    }
+
+    #[test]
+    fn obstacles_leave_a_way_through() {
+        let mut islands = 0;
+        for &style in TrackStyle::ALL.iter() {
+            for seed in 0..8 {
+                for &(min_radius, max_radius) in [(1, 2), (4, 4), (2, 7)].iter() {
+                    let width = WidthProfile::new(min_radius, max_radius, 3, seed);
+                    let with =
+                        TrackWorld::new(width, Obstacles::new(1.0, seed), style.generator(seed));
+                    let without = TrackWorld::new(width, Obstacles::none(), style.generator(seed));
+                    assert_eq!(reached_rows(&with, 150), (-150, 150), "{} {}", style, seed);
+                    let mut blocked = 0;
+                    for y in -150..=150 {
+                        let open = (-300..300)
+                            .map(|x| with.is_open(Vector::new(x, y)))
+                            .collect::<Vec<_>>();
+                        // Where the path is split, a row has more than one run
+                        // of open cells.
+                        let runs = open.windows(2).filter(|pair| !pair[0] && pair[1]).count();
+                        if runs > 1 {
+                            islands += 1;
+                        }
+                        blocked += (-300..300)
+                            .filter(|&x| {
+                                let pos = Vector::new(x, y);
+                                without.is_open(pos) && !with.is_open(pos)
+                            })
+                            .count();
+                    }
+                    assert!(blocked > 0, "{} {} {}", style, seed, min_radius);
+                }
+            }
+        }
+        assert!(islands > 0);
+    }
+
+    #[test]
+    fn no_density_places_nothing() {
+        let obstacles = Obstacles::new(0.0, 3);
+        for slot in -50..50 {
+            let cells = obstacles.blocked(slot, |n| Vector::new(0, n), |_| 5);
+            assert!(cells.is_empty());
+        }
+        let obstacles = Obstacles::new(1.0, 3);
+        assert!(obstacles
+            .blocked(0, |n| Vector::new(0, n), |_| 5)
+            .is_empty());
+    }
+}
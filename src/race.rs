@@ -0,0 +1,378 @@
+use crate::driver::Driver;
+use crate::racetrack::Racetrack;
+use crate::vector::{Traversal, Vector};
+
+// A race ends if the racer goes this many ticks without raising its score.
+pub const STAGNATION_LIMIT: u32 = 50;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum EndCause {
+    Crash,
+    Stagnation,
+    // The racer went the laps of a circuit or crossed a finish line. It did
+    // better the sooner it did.
+    Finish,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RaceOutcome {
+    // How far north the racer was when the race ended, up to the finish line
+    // if the corridor has one. On a circuit, how many checkpoints it passed,
+    // counting each lap. On a drawn track with a finish line, how much closer
+    // to the line the racer got.
+    pub score: i32,
+    pub time: usize,
+    pub end: EndCause,
+    // The first wall the racer hit, relative to the start.
+    pub crash_point: Option<Vector>,
+}
+
+// One racer on a track. The position is relative to the start.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Race {
+    track: Racetrack,
+    pos: Vector,
+    vel: Vector,
+    time: usize,
+    max_score: i32,
+    since_improved: u32,
+    // The next checkpoint to pass on a circuit, and how many have been passed.
+    next_checkpoint: usize,
+    checkpoints_passed: i32,
+    crossed_finish: bool,
+    outcome: Option<RaceOutcome>,
+    traversal: Traversal,
+}
+
+impl Race {
+    pub fn new(track: &Racetrack) -> Race {
+        Race::with_traversal(track, Traversal::default())
+    }
+
+    // The traversal decides which cells a move passes through.
+    pub fn with_traversal(track: &Racetrack, traversal: Traversal) -> Race {
+        let mut track = track.clone();
+        track.translate(Vector::ORIGIN);
+        Race {
+            vel: track.start_vel(),
+            track,
+            pos: Vector::ORIGIN,
+            time: 0,
+            max_score: 0,
+            since_improved: 0,
+            next_checkpoint: 1,
+            checkpoints_passed: 0,
+            crossed_finish: false,
+            outcome: None,
+            traversal,
+        }
+    }
+
+    // Runs one tick. Returns the outcome once the race is over.
+    pub fn step<D: Driver + ?Sized>(&mut self, driver: &mut D) -> Option<RaceOutcome> {
+        if self.outcome.is_some() {
+            return self.outcome;
+        }
+        self.vel = self.vel + driver.accel(self.vel, &self.track);
+        let mut hit = None;
+        for pt in self.traversal.pts(Vector::ORIGIN, self.vel) {
+            if self.track.get(pt) == Some(false) {
+                hit = Some(pt);
+                break;
+            }
+            self.pass_checkpoints(pt);
+            // Whatever is past the finish does not matter.
+            if self.finished() {
+                break;
+            }
+        }
+        if let Some(pt) = hit {
+            self.track.translate(pt);
+            self.pos = self.pos + pt;
+            return self.end(EndCause::Crash);
+        }
+        self.pos = self.pos + self.vel;
+        self.track.translate(self.vel);
+        if self.score() > self.max_score {
+            self.max_score = self.score();
+            self.since_improved = 0;
+        } else if self.since_improved > STAGNATION_LIMIT {
+            return self.end(EndCause::Stagnation);
+        } else {
+            self.since_improved += 1;
+        }
+        self.time = self.time.saturating_add(1);
+        if self.finished() {
+            return self.end(EndCause::Finish);
+        }
+        if let Some(false) = self.track.get(Vector::ORIGIN) {
+            return self.end(EndCause::Crash);
+        }
+        None
+    }
+
+    pub fn run<D: Driver + ?Sized>(&mut self, driver: &mut D) -> RaceOutcome {
+        loop {
+            if let Some(outcome) = self.step(driver) {
+                return outcome;
+            }
+        }
+    }
+
+    // Counts the checkpoints of a circuit or the finish line of other tracks
+    // at the point relative to the racer.
+    fn pass_checkpoints(&mut self, pt: Vector) {
+        if self.track.is_finish(pt) {
+            self.crossed_finish = true;
+        }
+        let circuit = match self.track.circuit() {
+            Some(circuit) => circuit,
+            None => return,
+        };
+        let pos = self.track.center() + pt;
+        let n_checkpoints = circuit.checkpoints().len();
+        let total = n_checkpoints as i32 * circuit.laps() as i32;
+        while self.checkpoints_passed < total && circuit.at_checkpoint(self.next_checkpoint, pos) {
+            self.checkpoints_passed += 1;
+            self.next_checkpoint = (self.next_checkpoint + 1) % n_checkpoints;
+        }
+    }
+
+    fn finished(&self) -> bool {
+        match self.track.circuit() {
+            Some(circuit) => {
+                self.checkpoints_passed
+                    >= circuit.checkpoints().len() as i32 * circuit.laps() as i32
+            }
+            None => self.crossed_finish,
+        }
+    }
+
+    pub fn score(&self) -> i32 {
+        if self.track.circuit().is_some() {
+            return self.checkpoints_passed;
+        }
+        let progress = self
+            .track
+            .map()
+            .and_then(|map| map.progress(self.track.center()));
+        match (progress, self.track.finish_line()) {
+            (Some(progress), _) => progress,
+            // Crashing into a wall on the finish line is not finishing.
+            (None, Some(finish_line)) if self.crossed_finish => finish_line,
+            (None, Some(finish_line)) => i32::min(self.pos.y, finish_line - 1),
+            (None, None) => self.pos.y,
+        }
+    }
+
+    fn end(&mut self, end: EndCause) -> Option<RaceOutcome> {
+        self.outcome = Some(RaceOutcome {
+            score: self.score(),
+            time: self.time,
+            end,
+            crash_point: if end == EndCause::Crash {
+                Some(self.pos)
+            } else {
+                None
+            },
+        });
+        self.outcome
+    }
+
+    pub fn pos(&self) -> Vector {
+        self.pos
+    }
+
+    pub fn vel(&self) -> Vector {
+        self.vel
+    }
+
+    pub fn time(&self) -> usize {
+        self.time
+    }
+
+    // The track is centered on the racer.
+    pub fn track(&self) -> &Racetrack {
+        &self.track
+    }
+
+    pub fn outcome(&self) -> Option<RaceOutcome> {
+        self.outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::GreedyDriver;
+    use crate::map::TrackMap;
+    use crate::racetrack::TrackShape;
+
+    use std::sync::Arc;
+
+    struct Constant(Vector);
+
+    impl Driver for Constant {
+        fn accel(&mut self, _vel: Vector, _track: &Racetrack) -> Vector {
+            self.0
+        }
+    }
+
+    #[test]
+    fn coasting_stagnates() {
+        let track = Racetrack::builder().view_dist(5).seed(2).build();
+        let outcome = Race::new(&track).run(&mut Constant(Vector::ORIGIN));
+        assert_eq!(outcome.end, EndCause::Stagnation);
+        assert_eq!(outcome.score, 0);
+        assert_eq!(outcome.time, STAGNATION_LIMIT as usize + 1);
+        assert_eq!(outcome.crash_point, None);
+    }
+
+    #[test]
+    fn supercover_catches_clipped_corners() {
+        // Find a track where a diagonal move only clips a wall at a corner.
+        let clips = |track: &Racetrack, vel: Vector| {
+            Vector::ORIGIN
+                .supercover_pts(vel)
+                .any(|pt| track.get(pt) == Some(false))
+                && !Vector::ORIGIN
+                    .segment_pts(vel)
+                    .any(|pt| track.get(pt) == Some(false))
+        };
+        let (track, vel) = (0..200)
+            .flat_map(|seed| {
+                let track = Racetrack::builder()
+                    .view_dist(8)
+                    .path_radius(2)
+                    .seed(seed)
+                    .build();
+                (-6..=6)
+                    .flat_map(|x| (-6..=6).map(move |y| Vector::new(x, y)))
+                    .filter(|&vel| clips(&track, vel))
+                    .map(|vel| (track.clone(), vel))
+                    .collect::<Vec<_>>()
+            })
+            .next()
+            .unwrap();
+        // Accelerate all at once.
+        let mut driver = Constant(vel);
+        assert_eq!(Race::new(&track).step(&mut driver), None);
+        let outcome = Race::with_traversal(&track, Traversal::Supercover)
+            .step(&mut driver)
+            .unwrap();
+        assert_eq!(outcome.end, EndCause::Crash);
+    }
+
+    // Follows the checkpoints of a circuit at most a cell per tick.
+    struct Follower {
+        target: usize,
+    }
+
+    impl Driver for Follower {
+        fn accel(&mut self, vel: Vector, track: &Racetrack) -> Vector {
+            let circuit = track.circuit().unwrap();
+            let checkpoints = circuit.checkpoints();
+            let mut target = checkpoints[self.target];
+            if target == track.center() {
+                self.target = (self.target + 1) % checkpoints.len();
+                target = checkpoints[self.target];
+            }
+            // Go along the longer way to the target, to keep near the path.
+            let d = target - track.center();
+            let want = if i32::abs(d.x) >= i32::abs(d.y) {
+                Vector::new(d.x.signum(), 0)
+            } else {
+                Vector::new(0, d.y.signum())
+            };
+            let accel = want - vel;
+            Vector::new(accel.x.signum(), accel.y.signum())
+        }
+    }
+
+    #[test]
+    fn circuit_laps_finish() {
+        let track = Racetrack::builder()
+            .view_dist(6)
+            .path_radius(3)
+            .seed(4)
+            .shape(TrackShape::Circuit)
+            .laps(2)
+            .build();
+        let n_checkpoints = track.circuit().unwrap().checkpoints().len() as i32;
+        let mut race = Race::with_traversal(&track, Traversal::Supercover);
+        let outcome = race.run(&mut Follower { target: 1 });
+        assert_eq!(outcome.end, EndCause::Finish);
+        assert_eq!(outcome.score, n_checkpoints * 2);
+        // Going the wrong way passes nothing.
+        let outcome = Race::new(&track).run(&mut Constant(Vector::new(0, -1)));
+        assert_eq!(outcome.score, 0);
+    }
+
+    #[test]
+    fn coasting_passes_no_checkpoints() {
+        for &path_radius in [1, 2, 4, 6, 8, 12].iter() {
+            for seed in 0..5 {
+                let track = Racetrack::builder()
+                    .view_dist(5)
+                    .path_radius(path_radius)
+                    .seed(seed)
+                    .shape(TrackShape::Circuit)
+                    .build();
+                let outcome = Race::new(&track).run(&mut Constant(Vector::ORIGIN));
+                assert_eq!(outcome.score, 0, "radius {} seed {}", path_radius, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn sideways_crashes() {
+        let track = Racetrack::builder().view_dist(10).seed(2).build();
+        let mut race = Race::new(&track);
+        let outcome = race.run(&mut Constant(Vector::new(1, 0)));
+        assert_eq!(outcome.end, EndCause::Crash);
+        let crash_point = outcome.crash_point.unwrap();
+        assert_eq!(crash_point, race.pos());
+        assert_eq!(race.track().get(Vector::ORIGIN), Some(false));
+        assert_eq!(race.step(&mut Constant(Vector::ORIGIN)), Some(outcome));
+    }
+
+    #[test]
+    fn map_finish_line() {
+        let map = TrackMap::parse(
+            "\
+velocity 1 0
+##########
+#   S   F#
+##########
+",
+        )
+        .unwrap();
+        let track = Racetrack::builder().view_dist(3).map(Arc::new(map)).build();
+        // The start velocity carries the racer to the finish.
+        let outcome = Race::new(&track).run(&mut Constant(Vector::ORIGIN));
+        assert_eq!(outcome.end, EndCause::Finish);
+        assert_eq!(outcome.score, 4);
+        assert_eq!(outcome.time, 4);
+        // Going the other way loses ground.
+        let outcome = Race::new(&track).run(&mut Constant(Vector::new(-1, 0)));
+        assert_eq!(outcome.end, EndCause::Crash);
+        assert_eq!(outcome.score, -4);
+    }
+
+    #[test]
+    fn corridor_finish_line() {
+        let track = Racetrack::builder()
+            .view_dist(6)
+            .seed(0)
+            .finish_dist(30)
+            .build();
+        let mut driver = GreedyDriver::new(3);
+        let outcome = Race::new(&track).run(&mut driver);
+        assert_eq!(outcome.end, EndCause::Finish);
+        assert_eq!(outcome.score, 30);
+        // The line is too far for coasting.
+        let outcome = Race::new(&track).run(&mut Constant(Vector::new(0, 1)));
+        assert_ne!(outcome.end, EndCause::Finish);
+        assert!(outcome.score < 30);
+    }
+}
@@ -1,26 +1,63 @@
-use crate::grid::Grid;
-use crate::rng::Rng;
-use crate::vector::{self, Vector};
+use crate::circuit::Circuit;
+use crate::generator::{TrackStyle, WidthProfile};
+use crate::map::TrackMap;
+use crate::obstacles::Obstacles;
+use crate::vector::Vector;
+use crate::world::{
+    self, Chunk, TrackWorld, OPEN, OPEN_AT_BOTH_EDGES, OPEN_AT_LEFT_EDGE, OPEN_AT_RIGHT_EDGE,
+};
 
-use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::SystemTime;
 
-#[derive(Clone, PartialEq, PartialOrd, Debug)]
+// A view of a track centered on a racer. Clones share the track, so it is only
+// generated once however many racers use it.
+#[derive(Clone)]
 pub struct Racetrack {
-    grid: Grid,
-    path_vel_x: i32,
-    path_pos: Vector,
-    path_radius: i32,
-    carving_ring: Box<[Vector]>,
+    ground: Ground,
+    // The absolute position of the center of the view.
+    center: Vector,
     view_dist: i32,
-    rng: Rng,
+    // The absolute row of the finish line of a corridor, if it ends.
+    finish_line: Option<i32>,
+}
+
+#[derive(Clone)]
+enum Ground {
+    Corridor {
+        world: Arc<TrackWorld>,
+        // The chunks covering the rows in view, starting with this index.
+        first_chunk: i32,
+        chunks: Vec<Arc<Chunk>>,
+    },
+    Circuit(Arc<Circuit>),
+    Map(Arc<TrackMap>),
+}
+
+// The kinds of track. A corridor goes north forever, and racers score by how
+// far north they get. A circuit is a closed loop, and racers score by the
+// checkpoints they pass on the way around.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum TrackShape {
+    Corridor,
+    Circuit,
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Debug)]
 pub struct RacetrackBuilder {
     view_dist: Option<i32>,
     path_radius: Option<i32>,
+    min_path_radius: Option<i32>,
+    width_rate: Option<i32>,
+    obstacle_density: Option<f64>,
     seed: Option<u64>,
+    shape: Option<TrackShape>,
+    style: Option<TrackStyle>,
+    laps: Option<u32>,
+    finish_dist: Option<i32>,
+    map: Option<Arc<TrackMap>>,
 }
 
 impl RacetrackBuilder {
@@ -28,7 +65,15 @@ impl RacetrackBuilder {
         RacetrackBuilder {
             view_dist: None,
             path_radius: None,
+            min_path_radius: None,
+            width_rate: None,
+            obstacle_density: None,
             seed: None,
+            shape: None,
+            style: None,
+            laps: None,
+            finish_dist: None,
+            map: None,
         }
     }
 
@@ -42,11 +87,60 @@ impl RacetrackBuilder {
         self
     }
 
+    // The path narrows down to this radius in places, and is never wider than
+    // the path radius.
+    pub fn min_path_radius(mut self, min_path_radius: i32) -> RacetrackBuilder {
+        self.min_path_radius = Some(min_path_radius);
+        self
+    }
+
+    // How many rows the path takes to change its radius by one.
+    pub fn width_rate(mut self, width_rate: i32) -> RacetrackBuilder {
+        self.width_rate = Some(width_rate);
+        self
+    }
+
+    // The chance of an obstacle in each stretch of the path, from 0 to 1.
+    pub fn obstacle_density(mut self, obstacle_density: f64) -> RacetrackBuilder {
+        self.obstacle_density = Some(obstacle_density);
+        self
+    }
+
     pub fn seed(mut self, seed: u64) -> RacetrackBuilder {
         self.seed = Some(seed);
         self
     }
 
+    pub fn shape(mut self, shape: TrackShape) -> RacetrackBuilder {
+        self.shape = Some(shape);
+        self
+    }
+
+    // How the path of a corridor winds.
+    pub fn style(mut self, style: TrackStyle) -> RacetrackBuilder {
+        self.style = Some(style);
+        self
+    }
+
+    // How many laps finish a race on a circuit.
+    pub fn laps(mut self, laps: u32) -> RacetrackBuilder {
+        self.laps = Some(laps);
+        self
+    }
+
+    // Ends a corridor with a finish line this many rows north of the start.
+    pub fn finish_dist(mut self, finish_dist: i32) -> RacetrackBuilder {
+        self.finish_dist = Some(finish_dist);
+        self
+    }
+
+    // Races on a drawn track instead of generating one, so the seed and the
+    // options for generating do not matter.
+    pub fn map(mut self, map: Arc<TrackMap>) -> RacetrackBuilder {
+        self.map = Some(map);
+        self
+    }
+
     pub fn build(self) -> Racetrack {
         Racetrack::from_builder(self)
     }
@@ -59,6 +153,9 @@ impl Racetrack {
 
     fn from_builder(builder: RacetrackBuilder) -> Racetrack {
         let view_dist = i32::abs(builder.view_dist.unwrap_or(20));
+        if let Some(map) = builder.map {
+            return Racetrack::with_map(map, view_dist);
+        }
         let path_radius = i32::abs(builder.path_radius.unwrap_or(4));
         let seed = builder.seed.unwrap_or_else(|| {
             // Seed the RNG from the system time now.
@@ -67,53 +164,173 @@ impl Racetrack {
                 .map(|d| d.as_secs())
                 .unwrap_or(0)
         });
-        let view_width = (view_dist * 2 + 1) as usize;
-        Racetrack {
-            grid: Grid::new(view_width, view_width),
-            path_vel_x: 0,
-            path_pos: Vector::ORIGIN,
+        let min_path_radius = i32::min(
+            path_radius,
+            i32::abs(builder.min_path_radius.unwrap_or(path_radius)),
+        );
+        let width = WidthProfile::new(
+            min_path_radius,
             path_radius,
-            carving_ring: make_ring(path_radius).into_boxed_slice(),
-            view_dist,
-            rng: Rng::with_seed(seed),
+            builder.width_rate.unwrap_or(4),
+            seed,
+        );
+        let obstacles = Obstacles::new(builder.obstacle_density.unwrap_or(0.0), seed);
+        match builder.shape.unwrap_or(TrackShape::Corridor) {
+            TrackShape::Corridor => {
+                let path = builder.style.unwrap_or(TrackStyle::Walk).generator(seed);
+                let world = Arc::new(TrackWorld::new(width, obstacles, path));
+                let mut track = Racetrack::with_world(world, view_dist);
+                track.finish_line = builder.finish_dist.map(|dist| i32::max(1, dist));
+                track
+            }
+            TrackShape::Circuit => {
+                let laps = builder.laps.unwrap_or(3);
+                let circuit = Circuit::generate(width, obstacles, laps, seed);
+                Racetrack::with_circuit(Arc::new(circuit), view_dist)
+            }
+        }
+    }
+
+    // Views the world from the start.
+    pub fn with_world(world: Arc<TrackWorld>, view_dist: i32) -> Racetrack {
+        let mut track = Racetrack {
+            ground: Ground::Corridor {
+                world,
+                first_chunk: 0,
+                chunks: Vec::new(),
+            },
+            center: Vector::ORIGIN,
+            view_dist: i32::abs(view_dist),
+            finish_line: None,
+        };
+        track.update_chunks();
+        track
+    }
+
+    // Views the circuit from the start.
+    pub fn with_circuit(circuit: Arc<Circuit>, view_dist: i32) -> Racetrack {
+        Racetrack {
+            center: circuit.start(),
+            ground: Ground::Circuit(circuit),
+            view_dist: i32::abs(view_dist),
+            finish_line: None,
+        }
+    }
+
+    // Views the drawn track from its start.
+    pub fn with_map(map: Arc<TrackMap>, view_dist: i32) -> Racetrack {
+        Racetrack {
+            center: map.start(),
+            ground: Ground::Map(map),
+            view_dist: i32::abs(view_dist),
+            finish_line: None,
         }
     }
 
     pub fn translate(&mut self, dpos: Vector) {
-        // TODO: inefficient
-        self.grid.clear();
-        self.path_pos = self.path_pos - dpos;
-        let limit = self.view_dist + self.path_radius;
-        while self.path_pos.y >= -limit {
-            self.move_path_south();
-        }
-        while self.path_pos.y <= limit {
-            let from = self.path_pos;
-            self.move_path_north();
-            for center in from.segment_pts(self.path_pos) {
-                if i32::abs(center.x) < self.view_dist + self.path_radius {
-                    for &pt in self.carving_ring.iter() {
-                        self.grid.v_get_mut(center + pt).map(|c| *c = true);
-                    }
-                }
+        self.center = self.center + dpos;
+        self.update_chunks();
+    }
+
+    fn update_chunks(&mut self) {
+        if let Ground::Corridor {
+            world,
+            first_chunk,
+            chunks,
+        } = &mut self.ground
+        {
+            let first = world::chunk_index(self.center.y - self.view_dist);
+            let last = world::chunk_index(self.center.y + self.view_dist);
+            if first == *first_chunk && chunks.len() == (last - first + 1) as usize {
+                return;
             }
+            let old_first = *first_chunk;
+            let old_chunks = std::mem::take(chunks);
+            *chunks = (first..=last)
+                .map(|index| match old_chunks.get((index - old_first) as usize) {
+                    Some(chunk) if index >= old_first => Arc::clone(chunk),
+                    _ => world.chunk(index),
+                })
+                .collect();
+            *first_chunk = first;
         }
     }
 
-    fn move_path_north(&mut self) {
-        self.path_pos.x += self.path_vel_x;
-        self.path_vel_x += (self.rng.forward() / (Rng::RAND_MAX / 3)) as i32 - 1;
-        self.path_pos.y += 1;
+    pub fn get(&self, pos: Vector) -> Option<bool> {
+        let view_dist = self.view_dist;
+        if i32::abs(pos.x) > view_dist || i32::abs(pos.y) > view_dist {
+            return None;
+        }
+        match &self.ground {
+            Ground::Corridor {
+                first_chunk,
+                chunks,
+                ..
+            } => {
+                let flag = if view_dist == 0 {
+                    OPEN_AT_BOTH_EDGES
+                } else if pos.x == -view_dist {
+                    OPEN_AT_LEFT_EDGE
+                } else if pos.x == view_dist {
+                    OPEN_AT_RIGHT_EDGE
+                } else {
+                    OPEN
+                };
+                let pos = self.center + pos;
+                let chunk = &chunks[(world::chunk_index(pos.y) - first_chunk) as usize];
+                Some(chunk.flags(pos) & flag != 0)
+            }
+            Ground::Circuit(circuit) => Some(circuit.is_open(self.center + pos)),
+            Ground::Map(map) => Some(map.is_open(self.center + pos)),
+        }
     }
 
-    fn move_path_south(&mut self) {
-        self.path_vel_x -= (self.rng.backward() / (Rng::RAND_MAX / 3)) as i32 - 1;
-        self.path_pos.x -= self.path_vel_x;
-        self.path_pos.y -= 1;
+    pub fn world(&self) -> Option<&Arc<TrackWorld>> {
+        match &self.ground {
+            Ground::Corridor { world, .. } => Some(world),
+            _ => None,
+        }
     }
 
-    pub fn get(&self, pos: Vector) -> Option<bool> {
-        self.grid.v_get(pos)
+    pub fn circuit(&self) -> Option<&Arc<Circuit>> {
+        match &self.ground {
+            Ground::Circuit(circuit) => Some(circuit),
+            _ => None,
+        }
+    }
+
+    pub fn map(&self) -> Option<&Arc<TrackMap>> {
+        match &self.ground {
+            Ground::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    // Whether the position relative to the center is on a finish line.
+    pub fn is_finish(&self, pos: Vector) -> bool {
+        match &self.ground {
+            Ground::Corridor { .. } => self.finish_line == Some(self.center.y + pos.y),
+            Ground::Circuit(_) => false,
+            Ground::Map(map) => map.is_finish(self.center + pos),
+        }
+    }
+
+    // The absolute row of the finish line of a corridor, if it ends.
+    pub fn finish_line(&self) -> Option<i32> {
+        self.finish_line
+    }
+
+    // The velocity racers start with.
+    pub fn start_vel(&self) -> Vector {
+        match &self.ground {
+            Ground::Map(map) => map.start_vel(),
+            _ => Vector::ORIGIN,
+        }
+    }
+
+    // The absolute position of the center of the view.
+    pub fn center(&self) -> Vector {
+        self.center
     }
 
     pub fn view_dist(&self) -> i32 {
@@ -121,14 +338,165 @@ impl Racetrack {
     }
 }
 
-fn make_ring(radius: i32) -> Vec<Vector> {
-    let radius = i32::abs(radius);
-    if radius >= 2 {
-        let hole: HashSet<Vector> = vector::circle_pts(radius - 2).collect();
-        vector::circle_pts(radius)
-            .filter(|pt| !hole.contains(pt))
-            .collect()
-    } else {
-        vector::circle_pts(radius).collect()
+// Views are equal when they show the same place on the same track.
+impl PartialEq for Racetrack {
+    fn eq(&self, other: &Racetrack) -> bool {
+        let same_ground = match (&self.ground, &other.ground) {
+            (Ground::Corridor { world: a, .. }, Ground::Corridor { world: b, .. }) => {
+                Arc::ptr_eq(a, b)
+            }
+            (Ground::Circuit(a), Ground::Circuit(b)) => Arc::ptr_eq(a, b),
+            (Ground::Map(a), Ground::Map(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        same_ground
+            && self.center == other.center
+            && self.view_dist == other.view_dist
+            && self.finish_line == other.finish_line
+    }
+}
+
+impl fmt::Debug for Racetrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ground = match &self.ground {
+            Ground::Corridor { .. } => "corridor",
+            Ground::Circuit(_) => "circuit",
+            Ground::Map(_) => "map",
+        };
+        f.debug_struct("Racetrack")
+            .field("ground", &ground)
+            .field("center", &self.center)
+            .field("view_dist", &self.view_dist)
+            .field("finish_line", &self.finish_line)
+            .finish()
+    }
+}
+
+impl FromStr for TrackShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TrackShape, String> {
+        match s {
+            "corridor" => Ok(TrackShape::Corridor),
+            "circuit" => Ok(TrackShape::Circuit),
+            _ => Err(format!("unknown track shape: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TrackShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TrackShape::Corridor => "corridor",
+            TrackShape::Circuit => "circuit",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Grid;
+    use crate::rng::Rng;
+    use crate::world::make_ring;
+
+    // The original translation, which clears the view and carves it again.
+    struct Recarved {
+        grid: Grid,
+        path_vel_x: i32,
+        path_pos: Vector,
+        path_radius: i32,
+        carving_ring: Vec<Vector>,
+        view_dist: i32,
+        rng: Rng,
+    }
+
+    impl Recarved {
+        fn new(view_dist: i32, path_radius: i32, seed: u64) -> Recarved {
+            let view_width = (view_dist * 2 + 1) as usize;
+            Recarved {
+                grid: Grid::new(view_width, view_width),
+                path_vel_x: 0,
+                path_pos: Vector::ORIGIN,
+                path_radius,
+                carving_ring: make_ring(path_radius),
+                view_dist,
+                rng: Rng::with_seed(seed),
+            }
+        }
+
+        fn translate(&mut self, dpos: Vector) {
+            self.grid.clear();
+            self.path_pos = self.path_pos - dpos;
+            let limit = self.view_dist + self.path_radius;
+            while self.path_pos.y >= -limit {
+                self.path_vel_x -= (self.rng.backward() / (Rng::RAND_MAX / 3)) as i32 - 1;
+                self.path_pos.x -= self.path_vel_x;
+                self.path_pos.y -= 1;
+            }
+            while self.path_pos.y <= limit {
+                let from = self.path_pos;
+                self.path_pos.x += self.path_vel_x;
+                self.path_vel_x += (self.rng.forward() / (Rng::RAND_MAX / 3)) as i32 - 1;
+                self.path_pos.y += 1;
+                for center in from.segment_pts(self.path_pos) {
+                    if i32::abs(center.x) < self.view_dist + self.path_radius {
+                        for &pt in self.carving_ring.iter() {
+                            if let Some(c) = self.grid.v_get_mut(center + pt) {
+                                *c = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn assert_same(track: &Racetrack, recarved: &Recarved, context: &str) {
+        let dist = track.view_dist() + 1;
+        for y in -dist..=dist {
+            for x in -dist..=dist {
+                let pos = Vector::new(x, y);
+                assert_eq!(
+                    track.get(pos),
+                    recarved.grid.v_get(pos),
+                    "{} at {}",
+                    context,
+                    pos
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn view_matches_recarving() {
+        let mut moves = Rng::with_seed(12);
+        for seed in 0..30 {
+            for &(view_dist, path_radius) in
+                [(0, 0), (1, 1), (3, 0), (5, 2), (8, 4), (20, 4), (6, 9)].iter()
+            {
+                let mut track = Racetrack::builder()
+                    .view_dist(view_dist)
+                    .path_radius(path_radius)
+                    .seed(seed)
+                    .build();
+                let mut recarved = Recarved::new(view_dist, path_radius, seed);
+                for step in 0..60 {
+                    // Mostly small moves, sometimes jumps past the view.
+                    let range = if moves.below(10) == 0 { 60 } else { 7 };
+                    let dpos = Vector::new(
+                        moves.below(range) as i32 - range as i32 / 2,
+                        moves.below(range) as i32 - range as i32 / 3,
+                    );
+                    track.translate(dpos);
+                    recarved.translate(dpos);
+                    let context = format!(
+                        "seed {}, view {}, radius {}, step {}",
+                        seed, view_dist, path_radius, step
+                    );
+                    assert_same(&track, &recarved, &context);
+                }
+            }
+        }
     }
 }
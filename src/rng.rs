@@ -12,6 +12,10 @@ impl Rng {
         }
     }
 
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
     // NOTE: I'm just hoping the wrapping arithmetic doesn't interfere with the math!
 
     pub fn forward(&mut self) -> u64 {
@@ -20,6 +24,17 @@ impl Rng {
         here
     }
 
+    // Returns a number less than n. This uses the high bits of the state, which
+    // are more random than the low bits.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.forward() as u128 * n as u128) / MODULUS as u128) as u64
+    }
+
+    // Returns a number in [0, 1).
+    pub fn fraction(&mut self) -> f64 {
+        self.forward() as f64 / MODULUS as f64
+    }
+
     pub fn backward(&mut self) -> u64 {
         // From https://stackoverflow.com/a/29585823/11815766
         self.state = INVERSE_MULTIPLIER.wrapping_mul(self.state.wrapping_sub(INCREMENT)) % MODULUS;
@@ -55,7 +70,21 @@ mod tests {
 
     #[test]
     fn inverse_multiplier_is_correct() {
-        assert!(MULTIPLIER * INVERSE_MULTIPLIER % MODULUS == 1);
+        assert_eq!(MULTIPLIER * INVERSE_MULTIPLIER % MODULUS, 1);
+    }
+
+    #[test]
+    fn below_is_in_range() {
+        let mut rng = Rng::with_seed(5);
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            seen[rng.below(3) as usize] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+        for _ in 0..100 {
+            assert!(rng.below(u64::MAX) < u64::MAX);
+            assert!(rng.fraction() < 1.0);
+        }
     }
 
     #[test]
@@ -0,0 +1,136 @@
+use crate::rng::Rng;
+
+use std::fmt;
+use std::str::FromStr;
+
+// Ways to pick parents for the next generation. Parents are picked by index
+// from a ranking sorted from best to worst.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Selection {
+    // Take turns picking from the better half.
+    Truncation,
+    // Pick the best of this many random entrants.
+    Tournament(usize),
+    // Pick with probability proportional to how much better the fitness is
+    // than the worst fitness, plus one.
+    Proportional,
+    // Pick with probability proportional to the number of entries ranked
+    // lower, plus one.
+    Rank,
+}
+
+impl Selection {
+    // Picks the index of a parent. The fitnesses must be sorted from best to
+    // worst, and the turn counts up from zero each generation.
+    pub fn pick(self, fitnesses: &[f64], turn: usize, rng: &mut Rng) -> usize {
+        let n = fitnesses.len();
+        assert!(n > 0, "selection from nothing");
+        match self {
+            Selection::Truncation => turn % usize::max(1, n / 2),
+            Selection::Tournament(size) => (0..usize::max(1, size))
+                .map(|_| rng.below(n as u64) as usize)
+                .min()
+                .unwrap(),
+            Selection::Proportional => {
+                let worst = fitnesses[n - 1];
+                pick_weighted(fitnesses.iter().map(|f| f - worst + 1.0), rng)
+            }
+            Selection::Rank => pick_weighted((1..=n).rev().map(|w| w as f64), rng),
+        }
+    }
+}
+
+fn pick_weighted<I: Iterator<Item = f64> + Clone>(weights: I, rng: &mut Rng) -> usize {
+    let total: f64 = weights.clone().sum();
+    let mut target = rng.fraction() * total;
+    let mut last = 0;
+    for (i, weight) in weights.enumerate() {
+        if target < weight {
+            return i;
+        }
+        target -= weight;
+        last = i;
+    }
+    // Rounding may leave a bit of the target over.
+    last
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Selection, String> {
+        match s {
+            "truncation" => return Ok(Selection::Truncation),
+            "proportional" => return Ok(Selection::Proportional),
+            "rank" => return Ok(Selection::Rank),
+            _ => (),
+        }
+        if let Some(size) = s.strip_prefix("tournament:") {
+            if let Ok(size) = usize::from_str(size) {
+                if size > 0 {
+                    return Ok(Selection::Tournament(size));
+                }
+            }
+        }
+        Err(format!("invalid selection: {}", s))
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Selection::Truncation => write!(f, "truncation"),
+            Selection::Tournament(size) => write!(f, "tournament:{}", size),
+            Selection::Proportional => write!(f, "proportional"),
+            Selection::Rank => write!(f, "rank"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(selection: Selection, fitnesses: &[f64]) -> Vec<usize> {
+        let mut rng = Rng::with_seed(77);
+        let mut counts = vec![0; fitnesses.len()];
+        for turn in 0..3000 {
+            counts[selection.pick(fitnesses, turn, &mut rng)] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn truncation_takes_turns() {
+        assert_eq!(
+            counts(Selection::Truncation, &[5.0, 4.0, 3.0, 2.0, 1.0]),
+            vec![1500, 1500, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn better_is_picked_more() {
+        let fitnesses = [10.0, 6.0, 3.0, 0.0];
+        for &selection in [
+            Selection::Tournament(3),
+            Selection::Proportional,
+            Selection::Rank,
+        ]
+        .iter()
+        {
+            let counts = counts(selection, &fitnesses);
+            for pair in counts.windows(2) {
+                assert!(pair[0] > pair[1], "{}: {:?}", selection, counts);
+            }
+            assert!(counts[3] > 0, "{}: {:?}", selection, counts);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        for name in ["truncation", "tournament:4", "proportional", "rank"].iter() {
+            assert_eq!(Selection::from_str(name).unwrap().to_string(), *name);
+        }
+        assert!(Selection::from_str("tournament:0").is_err());
+    }
+}
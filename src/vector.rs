@@ -2,6 +2,7 @@ use std::fmt;
 use std::iter;
 use std::mem;
 use std::ops;
+use std::str::FromStr;
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash, Debug)]
 pub struct Vector {
@@ -30,6 +31,38 @@ impl Vector {
             done: false,
         }
     }
+
+    // Every cell the segment between the cell centers touches, in order. Where
+    // the segment passes exactly through a corner, the cells on both sides of
+    // the corner are included before the one across it.
+    pub fn supercover_pts(self, other: Vector) -> SupercoverPts {
+        SupercoverPts {
+            pos: self,
+            step: Vector::new((other.x - self.x).signum(), (other.y - self.y).signum()),
+            dx: i64::from(i32::abs(other.x - self.x)),
+            dy: i64::from(i32::abs(other.y - self.y)),
+            ix: 0,
+            iy: 0,
+            pending: [None, None],
+            done: false,
+        }
+    }
+
+    // The cells of a Bresenham line between the cells, in order. Each cell is
+    // a king's move from the one before.
+    pub fn bresenham_pts(self, other: Vector) -> BresenhamPts {
+        let dx = i64::from(i32::abs(other.x - self.x));
+        let dy = -i64::from(i32::abs(other.y - self.y));
+        BresenhamPts {
+            pos: self,
+            to: other,
+            step: Vector::new((other.x - self.x).signum(), (other.y - self.y).signum()),
+            dx,
+            dy,
+            err: dx + dy,
+            done: false,
+        }
+    }
 }
 
 impl ops::Add for Vector {
@@ -106,6 +139,166 @@ impl Iterator for SegmentPts {
 
 impl iter::FusedIterator for SegmentPts {}
 
+#[derive(Clone, PartialEq)]
+pub struct SupercoverPts {
+    pos: Vector,
+    step: Vector,
+    dx: i64,
+    dy: i64,
+    // How many steps have been taken along each axis.
+    ix: i64,
+    iy: i64,
+    // Cells beside a corner that still need to be returned.
+    pending: [Option<Vector>; 2],
+    done: bool,
+}
+
+impl Iterator for SupercoverPts {
+    type Item = Vector;
+
+    fn next(&mut self) -> Option<Vector> {
+        if let Some(pt) = self.pending[0].take() {
+            self.pending.swap(0, 1);
+            return Some(pt);
+        }
+        if self.done {
+            return None;
+        }
+        let here = self.pos;
+        if self.ix >= self.dx && self.iy >= self.dy {
+            self.done = true;
+            return Some(here);
+        }
+        // Compare where the segment crosses the next vertical and horizontal
+        // cell borders, scaled to stay in integers.
+        let decision = (1 + 2 * self.ix) * self.dy - (1 + 2 * self.iy) * self.dx;
+        if decision == 0 {
+            self.pending = [
+                Some(Vector::new(here.x + self.step.x, here.y)),
+                Some(Vector::new(here.x, here.y + self.step.y)),
+            ];
+            self.pos = here + self.step;
+            self.ix += 1;
+            self.iy += 1;
+        } else if decision < 0 {
+            self.pos.x += self.step.x;
+            self.ix += 1;
+        } else {
+            self.pos.y += self.step.y;
+            self.iy += 1;
+        }
+        Some(here)
+    }
+}
+
+impl iter::FusedIterator for SupercoverPts {}
+
+#[derive(Clone, PartialEq)]
+pub struct BresenhamPts {
+    pos: Vector,
+    to: Vector,
+    step: Vector,
+    dx: i64,
+    dy: i64,
+    err: i64,
+    done: bool,
+}
+
+impl Iterator for BresenhamPts {
+    type Item = Vector;
+
+    fn next(&mut self) -> Option<Vector> {
+        if self.done {
+            return None;
+        }
+        let here = self.pos;
+        if here == self.to {
+            self.done = true;
+            return Some(here);
+        }
+        let e2 = 2 * self.err;
+        if e2 >= self.dy {
+            self.err += self.dy;
+            self.pos.x += self.step.x;
+        }
+        if e2 <= self.dx {
+            self.err += self.dx;
+            self.pos.y += self.step.y;
+        }
+        Some(here)
+    }
+}
+
+impl iter::FusedIterator for BresenhamPts {}
+
+// Ways to find the cells a move passes through, which decide whether it hits a
+// wall.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub enum Traversal {
+    // Steps of unit length, rounded to cells. This can skip cells that a
+    // diagonal move clips.
+    #[default]
+    Stepped,
+    // Every cell the move touches.
+    Supercover,
+    // A Bresenham line.
+    Bresenham,
+}
+
+impl Traversal {
+    pub fn pts(self, from: Vector, to: Vector) -> TraversalPts {
+        match self {
+            Traversal::Stepped => TraversalPts::Stepped(from.segment_pts(to)),
+            Traversal::Supercover => TraversalPts::Supercover(from.supercover_pts(to)),
+            Traversal::Bresenham => TraversalPts::Bresenham(from.bresenham_pts(to)),
+        }
+    }
+}
+
+impl FromStr for Traversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Traversal, String> {
+        match s {
+            "stepped" => Ok(Traversal::Stepped),
+            "supercover" => Ok(Traversal::Supercover),
+            "bresenham" => Ok(Traversal::Bresenham),
+            _ => Err(format!("unknown traversal: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Traversal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Traversal::Stepped => "stepped",
+            Traversal::Supercover => "supercover",
+            Traversal::Bresenham => "bresenham",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum TraversalPts {
+    Stepped(SegmentPts),
+    Supercover(SupercoverPts),
+    Bresenham(BresenhamPts),
+}
+
+impl Iterator for TraversalPts {
+    type Item = Vector;
+
+    fn next(&mut self) -> Option<Vector> {
+        match self {
+            TraversalPts::Stepped(pts) => pts.next(),
+            TraversalPts::Supercover(pts) => pts.next(),
+            TraversalPts::Bresenham(pts) => pts.next(),
+        }
+    }
+}
+
+impl iter::FusedIterator for TraversalPts {}
+
 #[derive(Clone, PartialEq)]
 pub struct CirclePts {
     pos: Vector,
@@ -148,3 +341,133 @@ impl Iterator for CirclePts {
 }
 
 impl iter::FusedIterator for CirclePts {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn random_segments() -> Vec<(Vector, Vector)> {
+        let mut rng = Rng::with_seed(31);
+        let mut coord = |range: u64| rng.below(range * 2 + 1) as i32 - range as i32;
+        let mut segments = (0..2000)
+            .map(|_| {
+                let from = Vector::new(coord(50), coord(50));
+                (from, from + Vector::new(coord(12), coord(12)))
+            })
+            .collect::<Vec<_>>();
+        // Exact diagonals and straight lines are the edge cases.
+        for &d in [0, 1, 3, 7].iter() {
+            for &(x, y) in [
+                (d, d),
+                (-d, d),
+                (d, -d),
+                (-d, -d),
+                (d, 0),
+                (0, -d),
+                (2 * d, d),
+            ]
+            .iter()
+            {
+                segments.push((Vector::new(5, -2), Vector::new(5 + x, -2 + y)));
+            }
+        }
+        segments
+    }
+
+    fn is_king_move(a: Vector, b: Vector) -> bool {
+        a != b && i32::abs(a.x - b.x) <= 1 && i32::abs(a.y - b.y) <= 1
+    }
+
+    fn is_rook_move(a: Vector, b: Vector) -> bool {
+        i32::abs(a.x - b.x) + i32::abs(a.y - b.y) == 1
+    }
+
+    #[test]
+    fn bresenham_is_8_connected() {
+        for (from, to) in random_segments() {
+            let pts = from.bresenham_pts(to).collect::<Vec<_>>();
+            assert_eq!(pts[0], from);
+            assert_eq!(*pts.last().unwrap(), to);
+            let steps = i32::max(i32::abs(to.x - from.x), i32::abs(to.y - from.y));
+            assert_eq!(pts.len(), steps as usize + 1, "{} to {}", from, to);
+            for pair in pts.windows(2) {
+                assert!(is_king_move(pair[0], pair[1]), "{} to {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn supercover_is_4_connected() {
+        for (from, to) in random_segments() {
+            let pts = from.supercover_pts(to).collect::<Vec<_>>();
+            assert_eq!(pts[0], from);
+            assert_eq!(*pts.last().unwrap(), to);
+            for (i, pair) in pts.windows(2).enumerate() {
+                assert!(is_king_move(pair[0], pair[1]), "{} to {}", from, to);
+                // Only the second cell beside a corner is diagonal from the
+                // cell before it, and it is beside the one before that.
+                assert!(
+                    is_rook_move(pair[0], pair[1]) || (i > 0 && is_rook_move(pts[i - 1], pair[1])),
+                    "{} to {}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn supercover_touches_every_cell() {
+        for (from, to) in random_segments() {
+            let pts = from.supercover_pts(to).collect::<Vec<_>>();
+            let mut sorted = pts.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), pts.len(), "{} to {}", from, to);
+            // Points along the segment, away from cell borders.
+            for i in 0..=1000 {
+                let t = i as f64 / 1000.0;
+                let x = from.x as f64 + (to.x - from.x) as f64 * t;
+                let y = from.y as f64 + (to.y - from.y) as f64 * t;
+                if (x.fract().abs() - 0.5).abs() < 1e-6 || (y.fract().abs() - 0.5).abs() < 1e-6 {
+                    continue;
+                }
+                let cell = Vector::new(x.round() as i32, y.round() as i32);
+                assert!(
+                    sorted.binary_search(&cell).is_ok(),
+                    "{} to {} misses {}",
+                    from,
+                    to,
+                    cell
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn supercover_includes_clipped_corners() {
+        let pts = Vector::ORIGIN
+            .supercover_pts(Vector::new(2, 2))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pts,
+            vec![
+                Vector::new(0, 0),
+                Vector::new(1, 0),
+                Vector::new(0, 1),
+                Vector::new(1, 1),
+                Vector::new(2, 1),
+                Vector::new(1, 2),
+                Vector::new(2, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn traversal_names() {
+        for name in ["stepped", "supercover", "bresenham"].iter() {
+            assert_eq!(Traversal::from_str(name).unwrap().to_string(), *name);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use crate::generator::{TrackGenerator, WidthProfile};
+use crate::obstacles::{self, Obstacles, OBSTACLE_SLOT};
+use crate::vector::{self, Vector};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+// How many rows are generated at a time.
+pub const CHUNK_HEIGHT: i32 = 64;
+
+// How many of the chunks generated last are kept after no view has them, in
+// case a racer comes back to them.
+const CACHED_CHUNKS: usize = 16;
+
+// Flags for how a cell was carved. A racetrack view does not count carving at
+// its left or right edge from ring points at the far side of a path center
+// beyond the edge, because it only carves centers strictly within its view
+// distance plus the path radius sideways.
+pub(crate) const OPEN: u8 = 1;
+pub(crate) const OPEN_AT_LEFT_EDGE: u8 = 2;
+pub(crate) const OPEN_AT_RIGHT_EDGE: u8 = 4;
+pub(crate) const OPEN_AT_BOTH_EDGES: u8 = 8;
+
+// The whole of the track for one seed, at absolute positions. The start is at
+// the origin. Rows are generated in chunks as they are first asked for, and the
+// chunks are shared between threads. A chunk is freed once no view has it and
+// it is not one of the last few generated, and generated again if needed.
+#[derive(Debug)]
+pub struct TrackWorld {
+    width: WidthProfile,
+    // What is carved around a center, for each radius from the narrowest.
+    carving: Box<[Carving]>,
+    obstacles: Obstacles,
+    path: Mutex<Box<dyn TrackGenerator>>,
+    chunks: RwLock<HashMap<i32, Weak<Chunk>>>,
+    recent: Mutex<VecDeque<Arc<Chunk>>>,
+}
+
+#[derive(Debug)]
+struct Carving {
+    // The cells around a center and their flags.
+    cells: Box<[(Vector, u8)]>,
+    // The first and last x of the cells on each row, from -radius to radius.
+    spans: Box<[(i32, i32)]>,
+}
+
+// The cells of the rows from y up to y + CHUNK_HEIGHT. Only the cells of each
+// row from the first to the last carved one are stored, so a chunk takes about
+// as much memory as the path in it however far the path has gone sideways.
+// Cells outside them are walls.
+#[derive(Debug)]
+pub struct Chunk {
+    y: i32,
+    // The x of the first stored cell of each row, and where its cells start.
+    // An extra entry at the end holds the number of cells.
+    rows: Box<[(i32, u32)]>,
+    cells: Box<[u8]>,
+}
+
+impl TrackWorld {
+    // The generator must start at the origin.
+    pub fn new(
+        width: WidthProfile,
+        obstacles: Obstacles,
+        path: Box<dyn TrackGenerator>,
+    ) -> TrackWorld {
+        TrackWorld {
+            width,
+            carving: (width.min_radius()..=width.max_radius())
+                .map(|radius| {
+                    // A ring only fills the path when every center has the
+                    // same radius.
+                    let pts = if width.is_constant() {
+                        make_ring(radius)
+                    } else {
+                        vector::circle_pts(radius).collect()
+                    };
+                    let mut spans = vec![(i32::MAX, i32::MIN); (radius * 2 + 1) as usize];
+                    for pt in pts.iter() {
+                        let span = &mut spans[(pt.y + radius) as usize];
+                        *span = (i32::min(span.0, pt.x), i32::max(span.1, pt.x));
+                    }
+                    Carving {
+                        cells: pts
+                            .into_iter()
+                            .map(|pt| (pt, ring_flags(pt, radius)))
+                            .collect(),
+                        spans: spans.into_boxed_slice(),
+                    }
+                })
+                .collect(),
+            obstacles,
+            path: Mutex::new(path),
+            chunks: RwLock::new(HashMap::new()),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn width(&self) -> WidthProfile {
+        self.width
+    }
+
+    pub fn obstacles(&self) -> Obstacles {
+        self.obstacles
+    }
+
+    pub fn is_open(&self, pos: Vector) -> bool {
+        self.chunk(chunk_index(pos.y)).flags(pos) & OPEN != 0
+    }
+
+    // Gets the chunk with the given index, counting north from the chunk with
+    // the start in it.
+    pub fn chunk(&self, index: i32) -> Arc<Chunk> {
+        if let Some(chunk) = self.live_chunk(index) {
+            return chunk;
+        }
+        // Only one thread generates at a time, so check whether another one
+        // generated the chunk while this one waited.
+        let mut path = self.path.lock().unwrap();
+        if let Some(chunk) = self.live_chunk(index) {
+            return chunk;
+        }
+        let chunk = Arc::new(self.generate(&mut **path, index * CHUNK_HEIGHT));
+        let mut chunks = self.chunks.write().unwrap();
+        chunks.insert(index, Arc::downgrade(&chunk));
+        // Forget freed chunks now and then, as often as the map doubles.
+        if chunks.len() >= 64 && chunks.len().is_power_of_two() {
+            chunks.retain(|_, chunk| chunk.strong_count() > 0);
+        }
+        drop(chunks);
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == CACHED_CHUNKS {
+            recent.pop_front();
+        }
+        recent.push_back(Arc::clone(&chunk));
+        chunk
+    }
+
+    fn live_chunk(&self, index: i32) -> Option<Arc<Chunk>> {
+        self.chunks.read().unwrap().get(&index)?.upgrade()
+    }
+
+    fn generate(&self, path: &mut dyn TrackGenerator, y: i32) -> Chunk {
+        let radius = self.width.max_radius();
+        let min_y = y - radius;
+        let max_y = y + CHUNK_HEIGHT - 1 + radius;
+        // Obstacles in slots around the chunk can reach into it, and need the
+        // path a little past the ends of their slots.
+        let reach = Obstacles::reach(radius);
+        let slots = obstacles::slot(y - reach)..=obstacles::slot(y + CHUNK_HEIGHT - 1 + reach);
+        let first_row = i32::min(min_y, slots.start() * OBSTACLE_SLOT - 2);
+        let last_row = i32::max(max_y, (slots.end() + 1) * OBSTACLE_SLOT + 1);
+        // Each step north covers centers on two rows.
+        while path.pos().y >= first_row {
+            path.move_south();
+        }
+        while path.pos().y < first_row - 1 {
+            path.move_north();
+        }
+        let mut centers = Vec::new();
+        // The x of the path on each row from the first.
+        let mut row_xs = Vec::new();
+        while path.pos().y <= last_row {
+            let from = path.pos();
+            path.move_north();
+            if from.y >= first_row {
+                row_xs.push(from.x);
+            }
+            centers.extend(
+                from.segment_pts(path.pos())
+                    .filter(|center| center.y >= min_y && center.y <= max_y),
+            );
+        }
+        let carving = |center: Vector| {
+            &self.carving[(self.width.radius(center.y) - self.width.min_radius()) as usize]
+        };
+        // Find what each row needs to store before carving it.
+        let mut spans = vec![(i32::MAX, i32::MIN); CHUNK_HEIGHT as usize];
+        for &center in centers.iter() {
+            let carving_spans = &carving(center).spans;
+            let carving_radius = carving_spans.len() as i32 / 2;
+            for (dy, &(from, to)) in (-carving_radius..).zip(carving_spans.iter()) {
+                if let Some(span) = spans.get_mut((center.y + dy - y) as usize) {
+                    *span = (
+                        i32::min(span.0, center.x + from),
+                        i32::max(span.1, center.x + to),
+                    );
+                }
+            }
+        }
+        let mut rows = Vec::with_capacity(spans.len() + 1);
+        let mut len = 0;
+        for &(from, to) in spans.iter() {
+            if from > to {
+                rows.push((0, len));
+            } else {
+                rows.push((from, len));
+                len += (to - from + 1) as u32;
+            }
+        }
+        rows.push((0, len));
+        let mut chunk = Chunk {
+            y,
+            rows: rows.into_boxed_slice(),
+            cells: vec![0; len as usize].into_boxed_slice(),
+        };
+        for center in centers {
+            for &(pt, flags) in carving(center).cells.iter() {
+                if let Some(i) = chunk.index(center + pt) {
+                    chunk.cells[i] |= flags;
+                }
+            }
+        }
+        let row_center = |n: i32| Vector::new(row_xs[(n - first_row) as usize], n);
+        let row_radius = |n: i32| self.width.radius(n);
+        for slot in slots {
+            for cell in self.obstacles.blocked(slot, row_center, row_radius) {
+                // Obstacles from the slots just south of the start can reach
+                // it, but racers must not start in a wall.
+                if cell == Vector::ORIGIN {
+                    continue;
+                }
+                if let Some(i) = chunk.index(cell) {
+                    chunk.cells[i] = 0;
+                }
+            }
+        }
+        chunk
+    }
+}
+
+impl Chunk {
+    // The flags of the cell at the absolute position, which must be in the rows
+    // of this chunk.
+    pub(crate) fn flags(&self, pos: Vector) -> u8 {
+        self.index(pos).map_or(0, |i| self.cells[i])
+    }
+
+    fn index(&self, pos: Vector) -> Option<usize> {
+        let row = pos.y.checked_sub(self.y)?;
+        if !(0..CHUNK_HEIGHT).contains(&row) {
+            return None;
+        }
+        let (min_x, start) = self.rows[row as usize];
+        let end = self.rows[row as usize + 1].1;
+        let x = pos.x as i64 - min_x as i64;
+        if (0..(end - start) as i64).contains(&x) {
+            Some(start as usize + x as usize)
+        } else {
+            None
+        }
+    }
+}
+
+// The index of the chunk containing row y.
+pub fn chunk_index(y: i32) -> i32 {
+    y.div_euclid(CHUNK_HEIGHT)
+}
+
+fn ring_flags(pt: Vector, radius: i32) -> u8 {
+    let mut flags = OPEN;
+    if pt.x < radius {
+        flags |= OPEN_AT_LEFT_EDGE;
+    }
+    if pt.x > -radius {
+        flags |= OPEN_AT_RIGHT_EDGE;
+    }
+    if pt.x < radius && pt.x > -radius {
+        flags |= OPEN_AT_BOTH_EDGES;
+    }
+    flags
+}
+
+pub(crate) fn make_ring(radius: i32) -> Vec<Vector> {
+    let radius = i32::abs(radius);
+    if radius >= 2 {
+        let hole: HashSet<Vector> = vector::circle_pts(radius - 2).collect();
+        vector::circle_pts(radius)
+            .filter(|pt| !hole.contains(pt))
+            .collect()
+    } else {
+        vector::circle_pts(radius).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::TrackStyle;
+
+    use std::thread;
+
+    #[test]
+    fn chunks_are_shared_between_threads() {
+        let world = Arc::new(TrackWorld::new(
+            WidthProfile::constant(4),
+            Obstacles::none(),
+            TrackStyle::Walk.generator(9),
+        ));
+        let handles = (0..4)
+            .map(|i| {
+                let world = Arc::clone(&world);
+                thread::spawn(move || {
+                    // Each thread goes a different way first.
+                    let order = if i & 1 == 1 { [3, -2, 0] } else { [-2, 0, 3] };
+                    order
+                        .iter()
+                        .map(|&index| Arc::as_ptr(&world.chunk(index)) as usize)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ptrs = handles
+            .into_iter()
+            .map(|handle| {
+                let mut ptrs = handle.join().unwrap();
+                ptrs.sort_unstable();
+                ptrs
+            })
+            .collect::<Vec<_>>();
+        ptrs.dedup();
+        assert_eq!(ptrs.len(), 1);
+    }
+
+    #[test]
+    fn generation_order_does_not_matter() {
+        for &style in TrackStyle::ALL.iter() {
+            let in_order = TrackWorld::new(
+                WidthProfile::constant(3),
+                Obstacles::new(0.5, 5),
+                style.generator(5),
+            );
+            let north_first = TrackWorld::new(
+                WidthProfile::constant(3),
+                Obstacles::new(0.5, 5),
+                style.generator(5),
+            );
+            for index in (-3..3).rev() {
+                north_first.chunk(index);
+            }
+            for y in -3 * CHUNK_HEIGHT..3 * CHUNK_HEIGHT {
+                for x in -40..40 {
+                    let pos = Vector::new(x, y);
+                    let open = in_order.is_open(pos);
+                    assert_eq!(open, north_first.is_open(pos), "{} {}", style, pos);
+                }
+            }
+            assert!(in_order.is_open(Vector::ORIGIN));
+        }
+    }
+
+    #[test]
+    fn memory_stays_bounded() {
+        let world = TrackWorld::new(
+            WidthProfile::constant(3),
+            Obstacles::none(),
+            TrackStyle::Walk.generator(2),
+        );
+        let mut generated = Vec::new();
+        for index in 0..400 {
+            // Like a view going north, which holds on to two chunks at a time.
+            let chunk = world.chunk(index);
+            let _behind = world.chunk(index - 1);
+            for n in 0..CHUNK_HEIGHT as usize {
+                let (min_x, start) = chunk.rows[n];
+                let stored = chunk.rows[n + 1].1 - start;
+                let open = (min_x..min_x + stored as i32)
+                    .filter(|&x| chunk.flags(Vector::new(x, chunk.y + n as i32)) & OPEN != 0)
+                    .count();
+                assert!(stored as usize <= open + 2, "{} {}", index, n);
+            }
+            generated.push(Arc::downgrade(&chunk));
+        }
+        // The path has gone far sideways by now.
+        assert!(generated.last().unwrap().upgrade().unwrap().rows[0].0.abs() > 1000);
+        let live = generated.iter().filter(|chunk| chunk.strong_count() > 0);
+        assert!(live.count() <= CACHED_CHUNKS);
+        assert!(world.chunks.read().unwrap().len() <= 4 * CACHED_CHUNKS);
+    }
+
+    #[test]
+    fn widths_vary_without_gaps() {
+        let world = TrackWorld::new(
+            WidthProfile::new(1, 5, 2, 4),
+            Obstacles::none(),
+            TrackStyle::Sine.generator(4),
+        );
+        let widths = (-2 * CHUNK_HEIGHT..2 * CHUNK_HEIGHT)
+            .map(|y| {
+                (-60..60)
+                    .filter(|&x| world.is_open(Vector::new(x, y)))
+                    .count()
+            })
+            .collect::<Vec<_>>();
+        assert!(widths.iter().all(|&width| width >= 3));
+        assert!(widths.iter().any(|&width| width <= 5));
+        assert!(widths.iter().any(|&width| width >= 11));
+    }
+}